@@ -8,18 +8,14 @@ BAM=$1
 output="./output/"
 
 del_data() {
- depth=$(samtools depth $BAM | head | awk '{depth[$3]++} END {for (d in depth) print d, depth[d]}' | sort -k2nr | head -1 | awk '{print $1}')
- if [ "$depth" -le 100 ]; then
-   ErrorModel=0
-    ./library/MOST-2.sh $BAM 10
- else
-   ErrorModel=1
-    ./library/MOST-2.sh $BAM 50
- fi
- ./library/RUST/jf_df/target/release/jf_df "$ErrorModel" & PID=$!
- ./library/RUST/RefBuild/target/release/RefBuild
+ mkdir -p "$output"
+ # jf_df 直接从带索引的 BAM 做 pileup；深度分布与错误模型由 --auto 统计选择，
+ # 记录在 ./output/run-report.txt
+ ./library/RUST/jf_df/target/release/jf_df --auto --bam "$BAM" --sites ./DB/2k.add
+ # 一致性序列由 jf_df 的等位计数表生成（深度 < 4 的位点屏蔽，同原 MOST-2.sh 的规则）
+ ./library/RUST/RefBuild/target/release/RefBuild --min-depth 4 \
+   --input all-stats.txt --output ./output/really-ref.fa --sample "$(basename "$BAM" .bam)"
  ./library/RUST/jf_score/target/release/jf_score 20 > ./output/2.txt
- wait $PID
  Cluster_abu
  echo -e "\nCluster_abundance"
  awk 'NR > 1 {if($2 > 0.5 && $5 != 0)print $1"\t"$5;else print $1"\t"$6}' DF-result-3.txt |awk '{if($2 < 0.005) next; print $0}'
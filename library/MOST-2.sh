@@ -8,49 +8,58 @@ fi
 input_bam=$1
 num_segments=$2
 
-#total_length=$(awk 'NR==2 {print length($0)}' /Stor/home/danielgroup/jiaofuxing/20240425-vp/2vp-ref/hebing2.fa)
-#kp
-#total_length=$(awk 'NR==2 {print length($0)}' /Stor/home/danielgroup/jiaofuxing/DB/MOST-KP-JD-ZhangTY/ref/Kp_reference.fasta)
-total_length=6000000
+output_prefix="./output/"
+sites_file="/Stor/home/danielgroup/jiaofuxing/YC/DB/2k.add"
+mkdir -p "$output_prefix"
 
+# 从 BAM 头部 @SQ 行读取序列名和长度（不再写死 total_length / ref_name）
+samtools view -H "$input_bam" | awk -F'\t' '$1 == "@SQ" {
+    name = ""; len = 0
+    for (k = 2; k <= NF; k++) {
+        if ($k ~ /^SN:/) name = substr($k, 4)
+        if ($k ~ /^LN:/) len = substr($k, 4)
+    }
+    print name "\t" len
+}' > "${output_prefix}contigs"
 
-segment_length=$((total_length / num_segments))
+total_length=$(awk '{s += $2} END {print s}' "${output_prefix}contigs")
+if [ -z "$total_length" ] || [ "$total_length" -le 0 ]; then
+    echo "No @SQ lines in $input_bam" >&2
+    exit 1
+fi
+segment_length=$(( (total_length + num_segments - 1) / num_segments ))
 
-output_prefix="./output/"
-ref_name="RIMD_2210633_NCBI"
-#ref_name="AP006725.1"  #Kp
-#ref_name="hi"
-#ref_name="Efm"
-mkdir -p "$output_prefix"
+# 位点列表里有、BAM 里没有的序列
+awk 'NR == FNR {seen[$1] = 1; next} !($1 in seen) {n[$1]++} END {for (c in n) printf "Warning: contig %s not in BAM header (%d sites skipped)\n", c, n[c]}' \
+    "${output_prefix}contigs" "$sites_file" >&2
 
-for ((i=1; i<=num_segments; i++))
+# 每条序列按自身长度切段，段号全局递增
+i=0
+while IFS=$'\t' read -r ref_name ref_length
 do
-    (
-        start=$(( (i - 1) * segment_length + 1 ))
-        end=$(( i * segment_length ))
-        output_file="${output_prefix}${i}.bam"
-        add_file="${output_prefix}${i}.add"
-        
-        # 切片BAM文件
-        sambamba -q slice "$input_bam" "$ref_name:$start-$end" > "$output_file"
-        #python /Stor/home/danielgroup/jiaofuxing/YC/library/slice.py  "$input_bam" "$ref_name:$start-$end" > "$output_file"
-
-        awk -v start="$start" -v end="$end" '{if ($2 >= start && $2 <= end) print}' \
-            /Stor/home/danielgroup/jiaofuxing/YC/DB/2k.add > "$add_file"
-        samtools mpileup -q 20 -Q 20 --no-output-ends --no-output-del --no-output-del  --no-output-ins --no-output-ins -A -a -l "$add_file" "$output_file" > "${output_prefix}${i}-out" 2> "${output_prefix}${i}-err.log" && rm "${output_prefix}${i}-err.log"
-
-
-        #awk -v start="$start" -v end="$end" '{if ($2 >= start && $2 <= end) print}' \
-            #/Stor/home/danielgroup/jiaofuxing/20240425-vp/ychao-data/2/newStr/all+RIMD.add > "$add_file"
-        # 运行mpileup并输出结果
-        #samtools mpileup -q 30 -Q 0  --no-output-ends --no-output-del --no-output-del  --no-output-ins --no-output-ins -A -a  --output-QNAME  -l "$add_file" "$output_file" > "${output_prefix}${i}-out" 2> "${output_prefix}${i}-err.log" && rm "${output_prefix}${i}-err.log"
-
-        #/Stor/home/danielgroup/jiaofuxing/20240425-vp/ychao-data/2/12-str.add
-    ) &
-done
+    for ((start=1; start<=ref_length; start+=segment_length))
+    do
+        i=$((i + 1))
+        end=$(( start + segment_length - 1 ))
+        if [ "$end" -gt "$ref_length" ]; then end=$ref_length; fi
+        (
+            output_file="${output_prefix}${i}.bam"
+            add_file="${output_prefix}${i}.add"
+
+            # 切片BAM文件
+            sambamba -q slice "$input_bam" "$ref_name:$start-$end" > "$output_file"
+
+            awk -v ref="$ref_name" -v start="$start" -v end="$end" '{if ($1 == ref && $2 >= start && $2 <= end) print}' \
+                "$sites_file" > "$add_file"
+            # 保留插入/缺失（+2AC、-1G 及占位 *），由 jf_df 计为独立等位
+            samtools mpileup -q 20 -Q 20 --no-output-ends -A -a -l "$add_file" "$output_file" > "${output_prefix}${i}-out" 2> "${output_prefix}${i}-err.log" && rm "${output_prefix}${i}-err.log"
+        ) < /dev/null &
+    done
+done < "${output_prefix}contigs"
+num_segments=$i
 wait
 
 # 合并输出结果
-seq 1 "$num_segments" | xargs -I{} cat "${output_prefix}{}-out" | tee >(awk '{print $2 "\t" $5}' > "${output_prefix}2lie-mp-samtool") | awk '{if ($4 <= 3) {print $2 "\t" "*"} else {print $2 "\t" $5}}' > "${output_prefix}hi"
+seq 1 "$num_segments" | xargs -I{} cat "${output_prefix}{}-out" | tee "${output_prefix}mp-samtool" | awk '{if ($4 <= 3) {print $1 ":" $2 "\t" "*"} else {print $1 ":" $2 "\t" $5}}' > "${output_prefix}hi"
 #seq 1 30 | xargs -I{} cat ./output/{}-out | awk '{print $5 "\t" $7}' > hebing
 echo "BAM analysis is OK"
\ No newline at end of file
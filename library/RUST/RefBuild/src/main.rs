@@ -1,54 +1,538 @@
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-fn process_file(input_path: &str, output_path: &str) -> Result<(), Box<dyn Error>> {
-    // 一次性把文件读进来
-    let data = fs::read(input_path)?;
-
-    let mut out = BufWriter::new(File::create(output_path)?);
-    out.write_all(b">really\n")?;
-
-    let mut start = 0;
-    while start < data.len() {
-        // 找到行尾
-        let end = data[start..]
-            .iter()
-            .position(|&b| b == b'\n')
-            .map_or(data.len(), |p| start + p);
-        let line = &data[start..end];
-
-        // 找第二列（第一个 tab 之后）
-        if let Some(tab) = line.iter().position(|&b| b == b'\t') {
-            let seq = &line[tab + 1..];
-
-            // 过滤 '*' 并转大写
-            let iter = seq.iter().copied().filter(|&b| b != b'*').map(|b| {
-                if b.is_ascii_lowercase() { b.to_ascii_uppercase() } else { b }
-            });
-
-            let mut iter = iter;
-            if let Some(first) = iter.next() {
-                if iter.all(|b| b == first) {
-                    out.write_all(&[first])?;
-                } else {
-                    out.write_all(b"-")?;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use site_id::SiteId;
+
+/// 一致性碱基的判定方式
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// 位点上只有一个等位（原来的行为，一个测序错误就会屏蔽位点）
+    Strict,
+    /// 最多的等位占总深度的比例不低于 `min_freq`，与第二名同数时屏蔽
+    Majority,
+    /// 最多的等位（优势菌株），与第二名同数时屏蔽
+    Dominant,
+}
+
+/// 输入输出与一致性序列的判定方式、逐位掩码条件
+struct Opts {
+    /// jf_df 的等位计数表，`-` 为标准输入；gzip 压缩的输入自动解压
+    input: String,
+    /// 一致性序列 FASTA，`-` 为标准输出；以 `.gz` 结尾时 gzip 压缩
+    output: String,
+    /// FASTA 标题中的样本名
+    sample: String,
+    /// 位点与逐位支持度旁注文件，缺省与输出同名（输出到标准输出时不写）
+    sites_path: Option<String>,
+    support_path: Option<String>,
+    mode: Mode,
+    /// `majority` 模式下主等位的最低频率
+    min_freq: f64,
+    /// 总深度低于它的位点输出 `-`（代替原来 `$4 <= 3` 的 awk 规则）
+    min_depth: usize,
+    /// 总深度高于它的位点输出 `-`，用来屏蔽重复/塌缩区域
+    max_depth: Option<usize>,
+    /// 一致性等位至少需要的读段数
+    min_support: usize,
+    /// 无法给出单一碱基的混合位点改用 IUPAC 兼并码
+    iupac: bool,
+    /// 计入兼并码的碱基的最低频率
+    iupac_min_freq: f64,
+    /// 另外输出次要菌株的一致性序列（`>minor`）
+    minor: bool,
+    /// 次要碱基的最低频率，低于它按测序错误处理
+    minor_min_freq: f64,
+}
+
+/// 解析数值参数；缺少或无法解析时报错，不悄悄退回缺省值
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.unwrap_or_default();
+    value.parse().map_err(|_| format!("{} 的取值无法解析: '{}'", flag, value))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Opts, String> {
+    let mut opts = Opts {
+        input: "./all-stats.txt".to_string(),
+        output: "./output/really-ref.fa".to_string(),
+        sample: "really".to_string(),
+        sites_path: None,
+        support_path: None,
+        mode: Mode::Strict,
+        min_freq: 0.8,
+        min_depth: 4,
+        max_depth: None,
+        min_support: 1,
+        iupac: false,
+        iupac_min_freq: 0.2,
+        minor: false,
+        minor_min_freq: 0.1,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => {
+                if let Some(path) = args.next() {
+                    opts.input = path;
+                }
+            }
+            "--output" => {
+                if let Some(path) = args.next() {
+                    opts.output = path;
                 }
-            } else {
-                out.write_all(b"-")?;
             }
+            "--sample" => {
+                if let Some(name) = args.next() {
+                    opts.sample = name;
+                }
+            }
+            "--sites" => opts.sites_path = args.next(),
+            "--support" => opts.support_path = args.next(),
+            "--mode" => {
+                opts.mode = match args.next().as_deref() {
+                    Some("strict") => Mode::Strict,
+                    Some("majority") => Mode::Majority,
+                    Some("dominant") => Mode::Dominant,
+                    other => {
+                        return Err(format!(
+                            "未知的一致性模式: {:?}（可选 strict / majority / dominant）",
+                            other.unwrap_or("")
+                        ));
+                    }
+                }
+            }
+            "--min-freq" => opts.min_freq = parse_value(&arg, args.next())?,
+            "--min-depth" => opts.min_depth = parse_value(&arg, args.next())?,
+            "--max-depth" => opts.max_depth = Some(parse_value(&arg, args.next())?),
+            "--min-support" => opts.min_support = parse_value(&arg, args.next())?,
+            "--iupac" => opts.iupac = true,
+            "--iupac-min-freq" => opts.iupac_min_freq = parse_value(&arg, args.next())?,
+            "--minor" => opts.minor = true,
+            "--minor-min-freq" => opts.minor_min_freq = parse_value(&arg, args.next())?,
+            _ => eprintln!("忽略未知参数: {}", arg),
+        }
+    }
+    Ok(opts)
+}
+
+/// 单个位点的一致性结果
+struct Call {
+    /// 一致性碱基，屏蔽时为 `-`
+    base: u8,
+    /// 最多的等位的读段数
+    support: usize,
+    depth: usize,
+    /// 多态位点上的主、次碱基，及次要碱基占两者的比例
+    split: Option<(u8, u8, f64)>,
+}
+
+impl Call {
+    /// 最多的等位占总深度的比例
+    fn freq(&self) -> f64 {
+        if self.depth > 0 { self.support as f64 / self.depth as f64 } else { 0.0 }
+    }
+}
+
+/// 由 jf_df 的计数列（`A:12,C:1,+AG:2`）和总深度求一致性碱基
+///
+/// 深度超出范围、不满足所选模式、该等位不是 ACGT 碱基（`N` 没有信息，
+/// jf_score 按缺失处理）或读段不足时为 `-`
+fn consensus(counts: &str, depth: usize, opts: &Opts) -> Call {
+    let alleles: Vec<(&[u8], usize)> = counts
+        .split(',')
+        .filter_map(|c| {
+            let (allele, n) = c.rsplit_once(':')?;
+            Some((allele.as_bytes(), n.parse().ok()?))
+        })
+        .collect();
+    // 同数时取计数列中靠前的（jf_df 按固定等位顺序输出）
+    let (top, support) = alleles
+        .iter()
+        .fold((&[][..], 0), |best, &(a, n)| if n > best.1 { (a, n) } else { best });
+    let mut call = Call { base: b'-', support, depth, split: None };
+
+    if depth < opts.min_depth || opts.max_depth.is_some_and(|m| depth > m) {
+        return call;
+    }
+    // `--minor` 时低于 `minor_min_freq` 的次要等位按测序错误处理：
+    // 只剩最多的等位时，strict 模式视同只有一个等位，两条记录都取它
+    let mut clean = false;
+    if opts.minor {
+        let kept = kept_alleles(&alleles, depth, opts);
+        call.split = split_alleles(&kept);
+        clean = matches!(kept[..], [(a, _)] if a == top);
+    }
+    // 与第二名同数时没有主等位：`--min-freq` 不高于 0.5 的 majority 模式也可能并列
+    let unique = alleles.iter().filter(|&&(_, n)| n == support).count() == 1;
+    let accepted = match opts.mode {
+        Mode::Strict => alleles.len() == 1 || clean,
+        Mode::Majority => unique && call.freq() >= opts.min_freq,
+        Mode::Dominant => unique,
+    };
+    if let &[base] = top
+        && is_base(base)
+        && accepted
+        && support >= opts.min_support
+    {
+        call.base = base.to_ascii_uppercase();
+    } else if opts.iupac {
+        call.base = ambiguity_code(&alleles, depth, opts);
+    }
+    call
+}
+
+fn is_base(b: u8) -> bool {
+    matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T')
+}
+
+/// 频率不低于 `minor_min_freq` 且读段数足够的等位，按读段数从多到少
+fn kept_alleles<'a>(alleles: &[(&'a [u8], usize)], depth: usize, opts: &Opts) -> Vec<(&'a [u8], usize)> {
+    let mut kept: Vec<(&[u8], usize)> = alleles
+        .iter()
+        .copied()
+        .filter(|&(_, n)| n >= opts.min_support && n as f64 >= opts.minor_min_freq * depth as f64)
+        .collect();
+    // 稳定排序：同数时保持计数列中的固定等位顺序
+    kept.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    kept
+}
+
+/// 多态位点上的主、次碱基
+///
+/// 在 [`kept_alleles`] 里取最多的两个，两者都是碱基时返回 (主, 次, 次 / (主 + 次))
+fn split_alleles(kept: &[(&[u8], usize)]) -> Option<(u8, u8, f64)> {
+    match kept[..] {
+        [(&[major], n1), (&[minor], n2), ..] if is_base(major) && is_base(minor) => Some((
+            major.to_ascii_uppercase(),
+            minor.to_ascii_uppercase(),
+            n2 as f64 / (n1 + n2) as f64,
+        )),
+        _ => None,
+    }
+}
+
+/// 混合位点的 IUPAC 兼并码
+///
+/// 频率不低于 `iupac_min_freq` 且读段数足够的等位都计入；其中有插入/缺失、
+/// 只剩一个碱基、或四种碱基都有（`N`，没有信息）时为 `-`
+fn ambiguity_code(alleles: &[(&[u8], usize)], depth: usize, opts: &Opts) -> u8 {
+    let mut mask = 0u8;
+    let mut n = 0;
+    for &(allele, count) in alleles {
+        if depth == 0
+            || count < opts.min_support
+            || (count as f64 / depth as f64) < opts.iupac_min_freq
+        {
+            continue;
         }
-        start = end + 1; // 跳过 '\n'
+        let bit = match allele {
+            b"A" | b"a" => 1,
+            b"C" | b"c" => 2,
+            b"G" | b"g" => 4,
+            b"T" | b"t" => 8,
+            _ => return b'-',
+        };
+        mask |= bit;
+        n += 1;
+    }
+    if n < 2 || mask == 15 {
+        return b'-';
     }
+    // 下标为 A=1、C=2、G=4、T=8 的组合
+    b"-ACMGRSVTWYHKDBN"[mask as usize]
+}
 
-    out.write_all(b"\n")?;
+/// 读入整个输入：`-` 为标准输入，以 gzip 魔数开头时解压
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    let mut raw = Vec::new();
+    if path == "-" {
+        io::stdin().lock().read_to_end(&mut raw)?;
+    } else {
+        File::open(path)?.read_to_end(&mut raw)?;
+    }
+    if !raw.starts_with(&[0x1f, 0x8b]) {
+        return Ok(raw);
+    }
+    let mut data = Vec::new();
+    MultiGzDecoder::new(&raw[..]).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// 写出整个输出：`-` 为标准输出，以 `.gz` 结尾时 gzip 压缩
+fn write_output(path: &str, data: &[u8]) -> io::Result<()> {
+    if path == "-" {
+        let mut out = io::stdout().lock();
+        out.write_all(data)?;
+        return out.flush();
+    }
+    let file = BufWriter::new(File::create(path)?);
+    if path.ends_with(".gz") {
+        let mut gz = GzEncoder::new(file, Compression::default());
+        gz.write_all(data)?;
+        gz.finish()?.flush()
+    } else {
+        let mut file = file;
+        file.write_all(data)?;
+        file.flush()
+    }
+}
+
+/// 旁注文件路径：显式给出的优先，否则为输出去掉 `.gz` 后换扩展名
+fn sidecar(given: &Option<String>, output: &str, ext: &str) -> Option<PathBuf> {
+    match given {
+        Some(path) => Some(PathBuf::from(path)),
+        None if output == "-" => None,
+        None => {
+            let base = output.strip_suffix(".gz").unwrap_or(output);
+            Some(Path::new(base).with_extension(ext))
+        }
+    }
+}
+
+/// 多态位点数，及其上次要碱基比例的中位数（次要菌株频率的估计，没有多态位点时为 0）
+fn minor_frequency(sites: &[(SiteId, Call)]) -> (usize, f64) {
+    let mut fracs: Vec<f64> = sites.iter().filter_map(|(_, c)| Some(c.split?.2)).collect();
+    fracs.sort_by(f64::total_cmp);
+    let median = match fracs.len() {
+        0 => 0.0,
+        n if n % 2 == 1 => fracs[n / 2],
+        n => (fracs[n / 2 - 1] + fracs[n / 2]) / 2.0,
+    };
+    (fracs.len(), median)
+}
+
+/// 输入为 jf_df 的等位计数表（all-stats.txt），第一列为位点标识（`contig:pos`），
+/// 第二列为计数，第五列为总深度；输出保持输入的位点顺序。`.sites` 旁注
+/// 逐行记录一致性序列每一位对应的位点，`.support` 旁注逐行记录
+/// 位点、一致性碱基、主等位读段数、总深度和主等位频率，供 jf_score 按置信度加权
+///
+/// `--minor` 时在多态位点上拆成两条记录：`>样本名` 取主碱基，`>样本名-minor` 取次要碱基，
+/// 其余位点两条相同；标题里的 `freq=` 为多态位点上次要碱基比例的中位数估计的菌株频率
+fn process(opts: &Opts) -> Result<(), Box<dyn Error>> {
+    let data = read_input(&opts.input)?;
+    let text = std::str::from_utf8(&data)?;
+    let mut sites: Vec<(SiteId, Call)> = Vec::new();
+
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let site: SiteId = fields[0].parse()?;
+        let depth = fields[4].parse().unwrap_or(0);
+        sites.push((site, consensus(fields[1], depth, opts)));
+    }
+
+    // 保持输入顺序（jf_df 按位点列表的顺序输出）：jf_score 有 `.sites` 旁注和数据库位点列表时
+    // 按位点标识对齐，没有时按位置逐位比较，按 contig 名重排会与数据库矩阵错位
+    let mut out = Vec::with_capacity(2 * sites.len() + 64);
+    if opts.minor {
+        let (polymorphic, minor_freq) = minor_frequency(&sites);
+        let major: Vec<u8> = sites.iter().map(|(_, c)| c.split.map_or(c.base, |s| s.0)).collect();
+        let minor: Vec<u8> = sites.iter().map(|(_, c)| c.split.map_or(c.base, |s| s.1)).collect();
+        writeln!(out, ">{} freq={:.3}", opts.sample, 1.0 - minor_freq)?;
+        out.extend_from_slice(&major);
+        writeln!(out)?;
+        writeln!(out, ">{}-minor freq={:.3}", opts.sample, minor_freq)?;
+        out.extend_from_slice(&minor);
+        writeln!(out)?;
+        eprintln!("多态位点 {} 个，次要菌株频率估计 {:.3}", polymorphic, minor_freq);
+    } else {
+        writeln!(out, ">{}", opts.sample)?;
+        out.extend(sites.iter().map(|(_, c)| c.base));
+        writeln!(out)?;
+    }
+    write_output(&opts.output, &out)?;
+
+    if let Some(path) = sidecar(&opts.sites_path, &opts.output, "sites") {
+        let mut ids = BufWriter::new(File::create(path)?);
+        for (site, _) in &sites {
+            writeln!(ids, "{}", site)?;
+        }
+        ids.flush()?;
+    }
+
+    if let Some(path) = sidecar(&opts.support_path, &opts.output, "support") {
+        let mut support = BufWriter::new(File::create(path)?);
+        for (site, c) in &sites {
+            writeln!(
+                support,
+                "{}\t{}\t{}\t{}\t{:.4}",
+                site, c.base as char, c.support, c.depth, c.freq()
+            )?;
+        }
+        support.flush()?;
+    }
     Ok(())
 }
 
 fn main() {
-    if let Err(e) = process_file("./output/hi", "./output/really-ref.fa") {
+    let opts = match parse_args(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("错误: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = process(&opts) {
         eprintln!("错误: {}", e);
-    } else {
-        println!("build---> 'really-ref.fa'");
+        std::process::exit(1);
+    } else if opts.output != "-" {
+        println!("build---> '{}'", opts.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(args: &[&str]) -> Opts {
+        parse_args(args.iter().map(|s| s.to_string())).unwrap()
     }
-}
\ No newline at end of file
+
+    fn base(counts: &str, opts: &Opts) -> char {
+        let depth = counts.split(',').filter_map(|c| c.rsplit_once(':')?.1.parse::<usize>().ok()).sum();
+        consensus(counts, depth, opts).base as char
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let err = |args: &[&str]| parse_args(args.iter().map(|s| s.to_string())).err();
+        assert_eq!(err(&["--min-freq", "abc"]).as_deref(), Some("--min-freq 的取值无法解析: 'abc'"));
+        assert!(err(&["--min-depth"]).is_some());
+        assert!(err(&["--max-depth", "-1"]).is_some());
+        assert!(err(&["--mode", "majorty"]).is_some());
+        let o = opts(&["--mode", "dominant", "--min-freq", "0.6", "--max-depth", "50"]);
+        assert!(o.mode == Mode::Dominant && o.min_freq == 0.6 && o.max_depth == Some(50));
+    }
+
+    #[test]
+    fn strict_mode() {
+        let o = opts(&[]);
+        assert_eq!(base("A:10", &o), 'A');
+        assert_eq!(base("a:10", &o), 'A');
+        assert_eq!(base("A:9,C:1", &o), '-');
+        // 深度不足、超过上限、读段不足
+        assert_eq!(base("A:3", &o), '-');
+        assert_eq!(base("A:60", &opts(&["--max-depth", "50"])), '-');
+        assert_eq!(base("A:4", &opts(&["--min-support", "5"])), '-');
+        // 插入/缺失不是碱基
+        assert_eq!(base("+AG:10", &o), '-');
+    }
+
+    #[test]
+    fn majority_and_dominant_modes() {
+        let majority = opts(&["--mode", "majority"]);
+        assert_eq!(base("A:9,C:1", &majority), 'A');
+        assert_eq!(base("A:7,C:3", &majority), '-');
+        // 并列不按等位顺序取
+        let loose = opts(&["--mode", "majority", "--min-freq", "0.5"]);
+        assert_eq!(base("A:3,C:3", &loose), '-');
+        assert_eq!(base("A:4,C:3", &loose), 'A');
+
+        let dominant = opts(&["--mode", "dominant"]);
+        assert_eq!(base("A:5,C:3,G:2", &dominant), 'A');
+        assert_eq!(base("A:3,C:3", &dominant), '-');
+        assert_eq!(base("-G:6,A:4", &dominant), '-');
+    }
+
+    #[test]
+    fn iupac_codes() {
+        let o = opts(&["--iupac"]);
+        assert_eq!(base("A:6,G:4", &o), 'R');
+        assert_eq!(base("C:6,T:3,G:1", &o), 'Y');
+        assert_eq!(base("A:4,C:3,G:3", &o), 'V');
+        // 低于频率下限的不计入，只剩一个碱基或含插入/缺失时屏蔽
+        assert_eq!(base("A:9,G:1", &o), '-');
+        assert_eq!(base("A:6,+T:4", &o), '-');
+        // 单一碱基仍直接输出
+        assert_eq!(base("T:8", &o), 'T');
+        // 四种碱基都有时不写 N
+        assert_eq!(base("A:3,C:3,G:2,T:2", &o), '-');
+    }
+
+    #[test]
+    fn n_is_never_a_consensus_base() {
+        assert_eq!(base("N:10", &opts(&[])), '-');
+        assert_eq!(base("n:10", &opts(&["--iupac"])), '-');
+        assert_eq!(base("N:8,A:2", &opts(&["--mode", "dominant"])), '-');
+        assert_eq!(base("A:8,N:2", &opts(&["--mode", "dominant"])), 'A');
+    }
+
+    #[test]
+    fn sidecar_paths() {
+        let given = Some("x/sites.txt".to_string());
+        assert_eq!(sidecar(&given, "-", "sites"), Some(PathBuf::from("x/sites.txt")));
+        assert_eq!(sidecar(&None, "-", "sites"), None);
+        assert_eq!(sidecar(&None, "out/ref.fa.gz", "sites"), Some(PathBuf::from("out/ref.sites")));
+        assert_eq!(sidecar(&None, "out/ref.fa", "support"), Some(PathBuf::from("out/ref.support")));
+    }
+
+    #[test]
+    fn input_gzip_is_sniffed() {
+        let dir = std::env::temp_dir().join(format!("refbuild_input_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = b"chr1:5\tA:9\t9\tA\t9\n".to_vec();
+
+        let plain = dir.join("plain.txt");
+        std::fs::write(&plain, &text).unwrap();
+        // 扩展名不决定格式：压缩内容放在 .txt 里，且由两个 gzip 成员拼接
+        let packed = dir.join("packed.txt");
+        let mut data = Vec::new();
+        for part in [&text[..6], &text[6..]] {
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(part).unwrap();
+            data.extend(gz.finish().unwrap());
+        }
+        std::fs::write(&packed, &data).unwrap();
+
+        assert_eq!(read_input(plain.to_str().unwrap()).unwrap(), text);
+        assert_eq!(read_input(packed.to_str().unwrap()).unwrap(), text);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn call(counts: &str, opts: &Opts) -> Call {
+        let depth = counts.split(',').filter_map(|c| c.rsplit_once(':')?.1.parse::<usize>().ok()).sum();
+        consensus(counts, depth, opts)
+    }
+
+    #[test]
+    fn minor_split() {
+        let o = opts(&["--minor"]);
+        // 次要等位低于 minor_min_freq：按测序错误处理，两条记录都取主碱基
+        let clean = call("A:100,C:1", &o);
+        assert_eq!((clean.base, clean.split.is_none()), (b'A', true));
+        // 真正的混合位点拆成主、次碱基
+        let mixed = call("A:100,C:20", &o);
+        let (major, minor, frac) = mixed.split.unwrap();
+        assert_eq!((mixed.base, major, minor), (b'-', b'A', b'C'));
+        assert!((frac - 20.0 / 120.0).abs() < 1e-12);
+        // 同数按计数列顺序；插入/缺失不拆
+        assert_eq!(call("C:50,A:50", &o).split.map(|s| (s.0, s.1)), Some((b'C', b'A')));
+        assert!(call("A:60,+T:40", &o).split.is_none());
+        // 读段数不足的次要等位不计
+        assert!(call("A:80,G:15", &opts(&["--minor", "--min-support", "20"])).split.is_none());
+        // 两个次要等位都超过下限时，最多的不算干净位点
+        assert_eq!(call("A:80,C:10,G:10", &o).base, b'-');
+    }
+
+    #[test]
+    fn minor_frequency_is_median() {
+        let o = opts(&["--minor"]);
+        let site = |counts: &str| ("chr1:1".parse().unwrap(), call(counts, &o));
+        let sites = vec![site("A:90,C:10"), site("A:10"), site("A:70,T:30"), site("G:80,A:20")];
+        let (n, freq) = minor_frequency(&sites);
+        assert_eq!(n, 3);
+        assert!((freq - 0.2).abs() < 1e-12);
+        // 偶数个取中间两个的平均；没有多态位点为 0
+        let both = vec![site("A:90,C:10"), site("A:70,T:30")];
+        assert!((minor_frequency(&both).1 - 0.2).abs() < 1e-12);
+        assert_eq!(minor_frequency(&[site("A:10")]), (0, 0.0));
+    }
+}
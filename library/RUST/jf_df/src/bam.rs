@@ -0,0 +1,574 @@
+// src/bam.rs
+//! 最小 BAM 读取：BGZF 解压、头部参考序列、BAI 区间查询
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap as HashMap;
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le_i32(b: &[u8]) -> i32 {
+    le_u32(b) as i32
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+/// BGZF 块读取器，支持按虚拟偏移定位
+struct Bgzf<R> {
+    inner: R,
+    block: Vec<u8>,
+    pos_in_block: usize,
+    block_coffset: u64,
+    next_coffset: u64,
+}
+
+impl<R: Read + Seek> Bgzf<R> {
+    fn new(inner: R) -> Self {
+        Bgzf { inner, block: Vec::new(), pos_in_block: 0, block_coffset: 0, next_coffset: 0 }
+    }
+
+    /// 读下一个块；文件结束返回 false
+    fn read_block(&mut self) -> io::Result<bool> {
+        self.block_coffset = self.next_coffset;
+        self.block.clear();
+        self.pos_in_block = 0;
+
+        let mut head = [0u8; 12];
+        match self.inner.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if head[0] != 31 || head[1] != 139 || head[2] != 8 || head[3] & 4 == 0 {
+            return Err(invalid("不是 BGZF 块"));
+        }
+        let xlen = le_u16(&head[10..]) as usize;
+        let mut extra = vec![0u8; xlen];
+        self.inner.read_exact(&mut extra)?;
+
+        // 在扩展字段里找 BC 子字段（BSIZE = 块总长 - 1）
+        let mut bsize = None;
+        let mut i = 0;
+        while i + 4 <= xlen {
+            let slen = le_u16(&extra[i + 2..]) as usize;
+            if extra[i] == b'B' && extra[i + 1] == b'C' && slen == 2 {
+                bsize = Some(le_u16(&extra[i + 4..]) as usize);
+            }
+            i += 4 + slen;
+        }
+        let bsize = bsize.ok_or_else(|| invalid("BGZF 块缺少 BC 字段"))?;
+
+        let rest = (bsize + 1)
+            .checked_sub(12 + xlen)
+            .filter(|&n| n >= 8)
+            .ok_or_else(|| invalid("BGZF 块长度异常"))?;
+        let mut cdata = vec![0u8; rest];
+        self.inner.read_exact(&mut cdata)?;
+        let isize = le_u32(&cdata[rest - 4..]) as usize;
+
+        self.block.reserve(isize);
+        DeflateDecoder::new(&cdata[..rest - 8]).read_to_end(&mut self.block)?;
+        if self.block.len() != isize {
+            return Err(invalid("BGZF 块解压长度不符"));
+        }
+        let mut crc = Crc::new();
+        crc.update(&self.block);
+        if crc.sum() != le_u32(&cdata[rest - 8..]) {
+            return Err(invalid("BGZF 块 CRC32 校验失败"));
+        }
+        self.next_coffset = self.block_coffset + bsize as u64 + 1;
+        Ok(true)
+    }
+
+    fn seek(&mut self, voffset: u64) -> io::Result<()> {
+        let coffset = voffset >> 16;
+        self.inner.seek(SeekFrom::Start(coffset))?;
+        self.next_coffset = coffset;
+        self.read_block()?;
+        self.pos_in_block = (voffset & 0xffff) as usize;
+        Ok(())
+    }
+
+    fn virtual_offset(&self) -> u64 {
+        if self.pos_in_block >= self.block.len() {
+            self.next_coffset << 16
+        } else {
+            (self.block_coffset << 16) | self.pos_in_block as u64
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Bgzf<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos_in_block >= self.block.len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.pos_in_block);
+        buf[..n].copy_from_slice(&self.block[self.pos_in_block..self.pos_in_block + n]);
+        self.pos_in_block += n;
+        Ok(n)
+    }
+}
+
+/// BAM 头部中的参考序列字典（与 `@SQ` 行一致）
+pub struct Header {
+    pub names: Vec<String>,
+    pub lengths: Vec<u64>,
+}
+
+impl Header {
+    fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != b"BAM\x01" {
+            return Err(invalid("不是 BAM 文件"));
+        }
+        let l_text = read_u32(r)? as usize;
+        io::copy(&mut r.take(l_text as u64), &mut io::sink())?;
+
+        let n_ref = read_u32(r)? as usize;
+        let mut names = Vec::with_capacity(n_ref);
+        let mut lengths = Vec::with_capacity(n_ref);
+        for _ in 0..n_ref {
+            let l_name = read_u32(r)? as usize;
+            let mut name = vec![0u8; l_name];
+            r.read_exact(&mut name)?;
+            name.pop(); // 去掉结尾 NUL
+            names.push(String::from_utf8_lossy(&name).into_owned());
+            lengths.push(read_u32(r)? as u64);
+        }
+        Ok(Header { names, lengths })
+    }
+
+    pub fn tid(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
+pub const FLAG_PAIRED: u16 = 0x1;
+pub const FLAG_PROPER_PAIR: u16 = 0x2;
+pub const FLAG_UNMAPPED: u16 = 0x4;
+pub const FLAG_REVERSE: u16 = 0x10;
+pub const FLAG_SECONDARY: u16 = 0x100;
+pub const FLAG_QCFAIL: u16 = 0x200;
+pub const FLAG_DUP: u16 = 0x400;
+
+pub const CIGAR_M: u8 = 0;
+pub const CIGAR_I: u8 = 1;
+pub const CIGAR_D: u8 = 2;
+pub const CIGAR_N: u8 = 3;
+pub const CIGAR_S: u8 = 4;
+pub const CIGAR_EQ: u8 = 7;
+pub const CIGAR_X: u8 = 8;
+
+/// 一条比对记录（只解码 pileup 需要的字段）
+pub struct Record {
+    pub tid: i32,
+    /// 0-based 比对起点
+    pub pos: i64,
+    pub mapq: u8,
+    pub flag: u16,
+    pub name: Vec<u8>,
+    /// (操作, 长度)
+    pub cigar: Vec<(u8, u32)>,
+    /// 解码后的大写碱基
+    pub seq: Vec<u8>,
+    /// 原始 Phred 质量（0xff 表示缺失）
+    pub qual: Vec<u8>,
+    /// MD 标签（用来还原缺失的参考碱基）
+    pub md: Option<Vec<u8>>,
+}
+
+impl Record {
+    /// 0-based 半开区间的参考终点
+    pub fn end(&self) -> i64 {
+        let ref_len: i64 = self
+            .cigar
+            .iter()
+            .filter(|(op, _)| matches!(*op, CIGAR_M | CIGAR_D | CIGAR_N | CIGAR_EQ | CIGAR_X))
+            .map(|&(_, n)| n as i64)
+            .sum();
+        self.pos + ref_len.max(1)
+    }
+
+    fn read<R: Read>(r: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<Self>> {
+        let mut len = [0u8; 4];
+        match r.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let block_size = u32::from_le_bytes(len) as usize;
+        if block_size < 32 {
+            return Err(invalid("BAM 记录过短"));
+        }
+        buf.resize(block_size, 0);
+        r.read_exact(buf)?;
+        let b = &buf[..];
+
+        let tid = le_i32(b);
+        let pos = le_i32(&b[4..]) as i64;
+        let l_read_name = b[8] as usize;
+        let mapq = b[9];
+        let n_cigar = le_u16(&b[12..]) as usize;
+        let flag = le_u16(&b[14..]);
+        let l_seq = le_u32(&b[16..]) as usize;
+
+        let mut p = 32;
+        let need = p + l_read_name + 4 * n_cigar + l_seq.div_ceil(2) + l_seq;
+        if need > b.len() {
+            return Err(invalid("BAM 记录长度不符"));
+        }
+        let name = b[p..p + l_read_name].strip_suffix(&[0]).unwrap_or(&[]).to_vec();
+        p += l_read_name;
+
+        let cigar = (0..n_cigar)
+            .map(|k| {
+                let v = le_u32(&b[p + 4 * k..]);
+                ((v & 0xf) as u8, v >> 4)
+            })
+            .collect();
+        p += 4 * n_cigar;
+
+        const CODES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+        let seq = (0..l_seq)
+            .map(|k| {
+                let byte = b[p + k / 2];
+                let code = if k % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                CODES[code as usize]
+            })
+            .collect();
+        p += l_seq.div_ceil(2);
+        let qual = b[p..p + l_seq].to_vec();
+        p += l_seq;
+        let md = find_aux_z(&b[p..], b"MD").map(<[u8]>::to_vec);
+
+        Ok(Some(Record { tid, pos, mapq, flag, name, cigar, seq, qual, md }))
+    }
+
+    /// 从 `pos` 起第 `offset` 个参考位置开始、被缺失掉的 `len` 个参考碱基
+    ///
+    /// 按 MD 标签的 `^` 段还原；没有 MD 或对不上时用 N 补齐
+    pub fn deleted_bases(&self, offset: u64, len: u64) -> Vec<u8> {
+        let mut out = vec![b'N'; len as usize];
+        let Some(md) = &self.md else {
+            return out;
+        };
+        let mut off = 0u64;
+        let mut num = 0u64;
+        let mut in_del = false;
+        for &c in md {
+            if c.is_ascii_digit() {
+                num = num * 10 + (c - b'0') as u64;
+                in_del = false;
+                continue;
+            }
+            off += num;
+            num = 0;
+            if c == b'^' {
+                in_del = true;
+                continue;
+            }
+            if in_del && off >= offset && off < offset + len {
+                out[(off - offset) as usize] = c.to_ascii_uppercase();
+            }
+            off += 1;
+        }
+        out
+    }
+}
+
+/// 在辅助字段中找 Z 类型的标签
+fn find_aux_z<'a>(mut aux: &'a [u8], tag: &[u8; 2]) -> Option<&'a [u8]> {
+    while aux.len() >= 3 {
+        let (t, ty) = (&aux[..2], aux[2]);
+        aux = &aux[3..];
+        let size = match ty {
+            b'A' | b'c' | b'C' => 1,
+            b's' | b'S' => 2,
+            b'i' | b'I' | b'f' => 4,
+            b'Z' | b'H' => {
+                let end = aux.iter().position(|&c| c == 0)?;
+                if t == tag && ty == b'Z' {
+                    return Some(&aux[..end]);
+                }
+                end + 1
+            }
+            b'B' => {
+                let sub = *aux.first()?;
+                let n = le_u32(aux.get(1..5)?) as usize;
+                let elem = match sub {
+                    b'c' | b'C' => 1,
+                    b's' | b'S' => 2,
+                    _ => 4,
+                };
+                5 + n * elem
+            }
+            _ => return None,
+        };
+        aux = aux.get(size..)?;
+    }
+    None
+}
+
+/// BAI 索引中单条参考序列的部分
+#[derive(Default)]
+struct RefIndex {
+    bins: HashMap<u32, Vec<(u64, u64)>>,
+    linear: Vec<u64>,
+}
+
+struct Bai {
+    refs: Vec<RefIndex>,
+}
+
+impl Bai {
+    fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != b"BAI\x01" {
+            return Err(invalid("不是 BAI 索引"));
+        }
+        let n_ref = read_u32(r)? as usize;
+        let mut refs = Vec::with_capacity(n_ref);
+        for _ in 0..n_ref {
+            let mut idx = RefIndex::default();
+            let n_bin = read_u32(r)?;
+            for _ in 0..n_bin {
+                let bin = read_u32(r)?;
+                let n_chunk = read_u32(r)?;
+                let mut chunks = Vec::with_capacity(n_chunk as usize);
+                for _ in 0..n_chunk {
+                    chunks.push((read_u64(r)?, read_u64(r)?));
+                }
+                idx.bins.insert(bin, chunks);
+            }
+            let n_intv = read_u32(r)?;
+            for _ in 0..n_intv {
+                idx.linear.push(read_u64(r)?);
+            }
+            refs.push(idx);
+        }
+        Ok(Bai { refs })
+    }
+
+    /// 与 [beg, end) 可能重叠的 BGZF 区段，已排序合并
+    fn chunks(&self, tid: usize, beg: u64, end: u64) -> Vec<(u64, u64)> {
+        let Some(idx) = self.refs.get(tid) else {
+            return Vec::new();
+        };
+        let min_off = idx
+            .linear
+            .get((beg >> 14) as usize)
+            .or(idx.linear.last())
+            .copied()
+            .unwrap_or(0);
+
+        let mut chunks: Vec<(u64, u64)> = reg2bins(beg, end)
+            .into_iter()
+            .filter_map(|bin| idx.bins.get(&bin))
+            .flatten()
+            .filter(|&&(_, e)| e > min_off)
+            .copied()
+            .collect();
+        chunks.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(chunks.len());
+        for (b, e) in chunks {
+            match merged.last_mut() {
+                Some(last) if b <= last.1 => last.1 = last.1.max(e),
+                _ => merged.push((b, e)),
+            }
+        }
+        merged
+    }
+}
+
+/// SAM 规范中的 reg2bins，区间为 0-based 半开
+fn reg2bins(beg: u64, end: u64) -> Vec<u32> {
+    let end = end.max(beg + 1) - 1;
+    let mut bins = vec![0u32];
+    for (offset, shift) in [(1u64, 26), (9, 23), (73, 20), (585, 17), (4681, 14)] {
+        for k in (offset + (beg >> shift))..=(offset + (end >> shift)) {
+            bins.push(k as u32);
+        }
+    }
+    bins
+}
+
+/// 带 BAI 索引的 BAM 读取器
+pub struct IndexedReader {
+    bgzf: Bgzf<BufReader<File>>,
+    pub header: Header,
+    index: Bai,
+    buf: Vec<u8>,
+}
+
+impl IndexedReader {
+    /// 打开 BAM，索引取 `<bam>.bai` 或同名 `.bai`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut bgzf = Bgzf::new(BufReader::new(File::open(path)?));
+        let header = Header::read(&mut bgzf)?;
+
+        let mut with_bai = path.as_os_str().to_owned();
+        with_bai.push(".bai");
+        let candidates = [PathBuf::from(with_bai), path.with_extension("bai")];
+        let bai_path = candidates.iter().find(|p| p.exists()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("找不到 {} 的 BAI 索引（请先 samtools index）", path.display()),
+            )
+        })?;
+        let index = Bai::read(&mut BufReader::new(File::open(bai_path)?))?;
+
+        Ok(IndexedReader { bgzf, header, index, buf: Vec::new() })
+    }
+
+    /// 依次回调与 0-based 半开区间 [beg, end) 重叠的记录
+    pub fn fetch(
+        &mut self,
+        tid: usize,
+        beg: u64,
+        end: u64,
+        mut f: impl FnMut(&Record),
+    ) -> io::Result<()> {
+        for (cbeg, cend) in self.index.chunks(tid, beg, end) {
+            self.bgzf.seek(cbeg)?;
+            while self.bgzf.virtual_offset() < cend {
+                let Some(rec) = Record::read(&mut self.bgzf, &mut self.buf)? else {
+                    break;
+                };
+                // 区段可能以其他序列的记录开头；有序 BAM 中越过区间即可停止
+                if rec.tid > tid as i32 || (rec.tid == tid as i32 && rec.pos as u64 >= end) {
+                    break;
+                }
+                if rec.tid == tid as i32 && rec.end() as u64 > beg {
+                    f(&rec);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/pileup.bam");
+
+    #[test]
+    fn reg2bins_levels() {
+        // 每层一个 bin：0、1、9、73、585、4681
+        assert_eq!(reg2bins(0, 1), [0, 1, 9, 73, 585, 4681]);
+        // 跨 16 kb 窗口时最细一层取两个 bin
+        assert_eq!(reg2bins(16383, 16385), [0, 1, 9, 73, 585, 4681, 4682]);
+        // 跨 128 kb 时倒数第二层也取两个
+        assert_eq!(reg2bins(131071, 131073), [0, 1, 9, 73, 585, 586, 4688, 4689]);
+        // 空区间按一个碱基处理
+        assert_eq!(reg2bins(20000, 20000), reg2bins(20000, 20001));
+    }
+
+    #[test]
+    fn linear_index_drops_earlier_chunks() {
+        let mut bins = HashMap::new();
+        bins.insert(0, vec![(10, 20), (50, 60)]);
+        bins.insert(4682, vec![(55, 70), (80, 90)]);
+        bins.insert(4681, vec![(30, 40)]);
+        let bai = Bai { refs: vec![RefIndex { bins, linear: vec![0, 45] }] };
+        // 窗口 1 的最小偏移为 45：bin 0 中结束于它之前的区段跳过，重叠区段合并
+        assert_eq!(bai.chunks(0, 16384, 16385), [(50, 70), (80, 90)]);
+        // 超出线性索引的窗口用最后一个值
+        assert_eq!(bai.chunks(0, 40000, 40001), [(50, 60)]);
+        assert!(bai.chunks(1, 0, 1).is_empty());
+    }
+
+    #[test]
+    fn fixture_index_cutoff() {
+        let reader = IndexedReader::open(FIXTURE).unwrap();
+        // bin 585 中的 r12、r11 起点早于窗口 2 的线性偏移，只剩 r10 所在的区段
+        assert_eq!(reader.index.chunks(0, 32999, 33000).len(), 1);
+        // 窗口 1：bin 585 的区段紧接着 r8、r9 所在的区段，合并成一段
+        assert_eq!(reader.index.chunks(0, 19999, 20004).len(), 1);
+    }
+
+    #[test]
+    fn fetch_across_bgzf_blocks() {
+        // 夹具每个 BGZF 块只有 64 字节，头部和每条记录都跨块
+        let mut reader = IndexedReader::open(FIXTURE).unwrap();
+        assert_eq!(reader.header.names, ["chr1", "chr2"]);
+        assert_eq!(reader.header.lengths, [40000, 500]);
+
+        let mut fetch = |tid, beg, end| {
+            let mut names = Vec::new();
+            reader
+                .fetch(tid, beg, end, |rec| names.push(String::from_utf8_lossy(&rec.name).into_owned()))
+                .unwrap();
+            names
+        };
+        let all = fetch(0, 0, 40000);
+        assert_eq!(all.len(), 13);
+        assert_eq!(all.last().map(String::as_str), Some("r10"));
+        // r12 的 N（剪接跳过）也算在比对范围内
+        assert_eq!(fetch(0, 16379, 16380), ["r12", "r11"]);
+        assert_eq!(fetch(0, 19999, 20000), ["r12", "r8", "r9"]);
+        assert_eq!(fetch(1, 0, 500), ["r13", "r14"]);
+        assert!(fetch(0, 500, 15000).is_empty());
+    }
+
+    #[test]
+    fn deleted_bases_from_md() {
+        let rec = Record {
+            tid: 0,
+            pos: 0,
+            mapq: 60,
+            flag: 0,
+            name: Vec::new(),
+            cigar: vec![(CIGAR_M, 4), (CIGAR_D, 2), (CIGAR_M, 3)],
+            seq: Vec::new(),
+            qual: Vec::new(),
+            md: Some(b"4^tT3".to_vec()),
+        };
+        assert_eq!(rec.deleted_bases(4, 2), b"TT");
+        assert_eq!(rec.deleted_bases(3, 2), b"NT");
+        assert_eq!(Record { md: None, ..rec }.deleted_bases(4, 2), b"NN");
+    }
+
+    #[test]
+    fn corrupt_block_fails_crc() {
+        let mut data = std::fs::read(FIXTURE).unwrap();
+        // 第一个块：BC 子字段紧跟在 12 字节头部之后，CRC32 位于块末尾前 8 字节
+        let bsize = le_u16(&data[16..]) as usize;
+        data[bsize + 1 - 8] ^= 1;
+        let err = Bgzf::new(io::Cursor::new(data)).read_block().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("CRC32"), "{}", err);
+    }
+}
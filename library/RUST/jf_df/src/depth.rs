@@ -0,0 +1,223 @@
+// src/depth.rs
+//! 由等位计数表估计深度分布，并据此选择测序错误模型参数
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::stats::ErrorModel;
+
+/// 高于此中位深度时估计过度离散并启用 beta-binomial（原 jacky14.sh 的分界）
+const HIGH_DEPTH: f64 = 100.0;
+/// 参与错误率估计的位点：深度至少这么多、非主等位比例不超过上限（排除真实多态）
+const ERR_MIN_DEPTH: usize = 10;
+const ERR_MAX_FRACTION: f64 = 0.2;
+/// 错误率下限，避免估计为 0 时任何次要等位都显著
+const MIN_ERROR_RATE: f64 = 1e-4;
+
+/// 深度直方图与非主等位读段累计
+#[derive(Default)]
+pub struct DepthProfile {
+    /// hist[d] = 深度为 d 的位点数
+    hist: Vec<u64>,
+    err_reads: u64,
+    err_depth: u64,
+    /// 参与错误率估计的位点数，及其非主等位比例 p 的 Σp、Σp²、Σ1/n
+    err_sites: u64,
+    sum_p: f64,
+    sum_p2: f64,
+    sum_inv_n: f64,
+}
+
+/// 深度分布摘要（深度为 0 的位点不计入中位数、众数和均值）
+pub struct DepthSummary {
+    pub sites: u64,
+    pub covered: u64,
+    pub breadth: f64,
+    pub median: f64,
+    pub mode: usize,
+    pub mean: f64,
+}
+
+impl DepthProfile {
+    pub fn add(&mut self, depth: usize, major: usize) {
+        if self.hist.len() <= depth {
+            self.hist.resize(depth + 1, 0);
+        }
+        self.hist[depth] += 1;
+
+        let minor = depth - major;
+        if depth >= ERR_MIN_DEPTH && minor as f64 <= ERR_MAX_FRACTION * depth as f64 {
+            let p = minor as f64 / depth as f64;
+            self.err_reads += minor as u64;
+            self.err_depth += depth as u64;
+            self.err_sites += 1;
+            self.sum_p += p;
+            self.sum_p2 += p * p;
+            self.sum_inv_n += 1.0 / depth as f64;
+        }
+    }
+
+    pub fn summary(&self) -> DepthSummary {
+        let sites: u64 = self.hist.iter().sum();
+        let covered = sites - self.hist.first().copied().unwrap_or(0);
+
+        let mut median = 0.0;
+        let mut mode = 0;
+        let mut mean = 0.0;
+        if covered > 0 {
+            let (lo, hi) = ((covered - 1) / 2, covered / 2);
+            let (mut lo_d, mut hi_d) = (None, None);
+            let mut seen = 0u64;
+            for (d, &n) in self.hist.iter().enumerate().skip(1) {
+                if n == 0 {
+                    continue;
+                }
+                if lo < seen + n && lo_d.is_none() {
+                    lo_d = Some(d);
+                }
+                if hi < seen + n && hi_d.is_none() {
+                    hi_d = Some(d);
+                }
+                seen += n;
+                if n > self.hist[mode] || mode == 0 {
+                    mode = d;
+                }
+                mean += (d as u64 * n) as f64;
+            }
+            median = (lo_d.unwrap_or(0) + hi_d.unwrap_or(0)) as f64 / 2.0;
+            mean /= covered as f64;
+        }
+
+        DepthSummary {
+            sites,
+            covered,
+            breadth: if sites > 0 { covered as f64 / sites as f64 } else { 0.0 },
+            median,
+            mode,
+            mean,
+        }
+    }
+
+    /// 根据深度分布选择错误模型：错误率取非多态位点上非主等位读段的合并比例；
+    /// 中位深度高于 [`HIGH_DEPTH`] 时再用矩估计求 beta-binomial 过度离散度
+    pub fn choose_model(&self, summary: &DepthSummary) -> ErrorModel {
+        let e = if self.err_depth > 0 {
+            (self.err_reads as f64 / self.err_depth as f64).max(MIN_ERROR_RATE)
+        } else {
+            0.01
+        };
+
+        let mut dispersion = 0.0;
+        if summary.median > HIGH_DEPTH && self.err_sites > 1 {
+            // 各位点比例的离散超出二项方差的部分归为过度离散：
+            // Σ(p - e)² ≈ Σ v/n · (1 + (n - 1)ρ)
+            let v = e * (1.0 - e);
+            let m = self.err_sites as f64;
+            let sq = self.sum_p2 - 2.0 * e * self.sum_p + e * e * m;
+            let excess = sq - v * self.sum_inv_n;
+            let scale = v * (m - self.sum_inv_n);
+            if scale > 0.0 {
+                dispersion = (excess / scale).clamp(0.0, 0.5);
+            }
+        }
+        ErrorModel { error_rate: e, dispersion }
+    }
+}
+
+/// 记录深度分布与最终使用的参数
+pub fn write_report(
+    path: &str,
+    summary: &DepthSummary,
+    model: &ErrorModel,
+    max_p: f64,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "sites\t{}", summary.sites)?;
+    writeln!(w, "covered_sites\t{}", summary.covered)?;
+    writeln!(w, "breadth\t{:.4}", summary.breadth)?;
+    writeln!(w, "depth_median\t{:.1}", summary.median)?;
+    writeln!(w, "depth_mode\t{}", summary.mode)?;
+    writeln!(w, "depth_mean\t{:.2}", summary.mean)?;
+    let name = if model.dispersion > 0.0 { "beta-binomial" } else { "binomial" };
+    writeln!(w, "error_model\t{}", name)?;
+    writeln!(w, "error_rate\t{:.3e}", model.error_rate)?;
+    writeln!(w, "dispersion\t{:.3e}", model.dispersion)?;
+    writeln!(w, "max_p\t{}", max_p)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 (深度, 主等位读段数) 建立深度分布
+    fn profile(sites: &[(usize, usize)]) -> DepthProfile {
+        let mut p = DepthProfile::default();
+        for &(depth, major) in sites {
+            p.add(depth, major);
+        }
+        p
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn summary_of_known_histograms() {
+        // 深度 0 不计入中位数、众数和均值；偶数个取中间两个的平均
+        let s = profile(&[(0, 0), (5, 5), (10, 10), (10, 10), (20, 20)]).summary();
+        assert_eq!((s.sites, s.covered, s.mode), (5, 4, 10));
+        assert!(close(s.breadth, 0.8) && close(s.median, 10.0) && close(s.mean, 11.25));
+
+        let s = profile(&[(3, 3), (9, 9), (7, 7)]).summary();
+        assert!(close(s.median, 7.0) && close(s.mean, 19.0 / 3.0));
+        // 众数并列时取较小的深度
+        assert_eq!(profile(&[(7, 7), (3, 3), (7, 7), (3, 3)]).summary().mode, 3);
+        let s = profile(&[(4, 4), (8, 8)]).summary();
+        assert!(close(s.median, 6.0));
+
+        let s = profile(&[(0, 0), (0, 0)]).summary();
+        assert_eq!((s.sites, s.covered, s.mode), (2, 0, 0));
+        assert!(close(s.median, 0.0) && close(s.breadth, 0.0));
+        assert_eq!(DepthProfile::default().summary().sites, 0);
+    }
+
+    #[test]
+    fn error_rate_from_non_polymorphic_sites() {
+        // 深度不足 10 或非主等位比例超过 0.2（真实多态）的位点不参与
+        let p = profile(&[(100, 98), (200, 199), (50, 30), (5, 4)]);
+        let model = p.choose_model(&p.summary());
+        assert!(close(model.error_rate, 3.0 / 300.0));
+        assert_eq!(model.dispersion, 0.0);
+
+        // 没有可用位点取 0.01，全无错误时取下限
+        let p = profile(&[(5, 4)]);
+        assert!(close(p.choose_model(&p.summary()).error_rate, 0.01));
+        let p = profile(&[(50, 50)]);
+        assert!(close(p.choose_model(&p.summary()).error_rate, MIN_ERROR_RATE));
+    }
+
+    #[test]
+    fn dispersion_only_at_high_depth() {
+        // 深度 1000，非主等位 0 与 40 交替：e = 0.02，
+        // ρ = (Σ(p - e)² - v Σ1/n) / (v (m - Σ1/n))，v = e(1 - e)
+        let sites: Vec<(usize, usize)> = (0..100).map(|i| (1000, if i % 2 == 0 { 1000 } else { 960 })).collect();
+        let p = profile(&sites);
+        let model = p.choose_model(&p.summary());
+        let (e, m) = (0.02, 100.0);
+        let v = e * (1.0 - e);
+        let expected = (m * 0.0004 - v * m / 1000.0) / (v * (m - m / 1000.0));
+        assert!(close(model.error_rate, e));
+        assert!(close(model.dispersion, expected), "{}", model.dispersion);
+
+        // 各位点比例相同：没有超出二项方差的离散
+        let p = profile(&vec![(1000, 990); 50]);
+        assert_eq!(p.choose_model(&p.summary()).dispersion, 0.0);
+
+        // 中位深度不超过 100 时不估计
+        let sites: Vec<(usize, usize)> = (0..100).map(|i| (100, if i % 2 == 0 { 100 } else { 96 })).collect();
+        let p = profile(&sites);
+        assert_eq!(p.choose_model(&p.summary()).dispersion, 0.0);
+    }
+}
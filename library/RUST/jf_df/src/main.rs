@@ -3,144 +3,591 @@
 
 use ahash::AHashMap as HashMap;
 use std::env;
-use std::fs::File;
-use std::io::{BufWriter, Write}; // 只用 io::Write
-use std::sync::Arc;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+mod bam;
+mod depth;
+mod mpileup;
+mod pileup;
+mod stats;
+use depth::DepthProfile;
+use mpileup::{Allele, Obs, Row};
+use pileup::PileupOpts;
+use stats::{fisher_exact, ErrorModel};
 
 use rayon::prelude::*;
-use dashmap::DashMap;
 use memmap2::Mmap;
 
-/// 处理单行 → (处理后文本, min_count)
-fn process_line(line: &str) -> Option<(String, usize)> {
-    let mut cols = line.splitn(3, '\t');
-    let col1 = cols.next()?;  // 第一列
-    let _col2 = cols.next()?.trim_end();  // 第二列（现在忽略）
-    let col3 = cols.next().unwrap_or("");  // 第三列（如果有）
+/// 文本输入每次并行处理的行数，内存占用与它成正比而与基因组大小无关
+const CHUNK_LINES: usize = 64 * 1024;
+/// `--auto` 且未给出 `--max-p` 时使用的错误模型 p 值上限
+const DEFAULT_MAX_P: f64 = 1e-3;
+/// `--auto` 在 BAM 模式下暂存位点的文件，写完结果后删除
+const SPOOL_PATH: &str = "./output/auto-sites.tmp";
+
+/// 运行参数
+struct Opts {
+    min_threshold: usize,
+    /// 链偏倚 Fisher p 值下限，低于它的位点不进 poc（0 表示不检验）
+    min_sb_p: f64,
+    /// 允许次要等位只出现在单条链上
+    keep_single_strand: bool,
+    /// 碱基质量下限，低于它的读段碱基不计数（缺省：文本输入 0，BAM 输入 20）
+    min_bq: Option<u8>,
+    /// 额外输出按碱基质量加权的计数
+    qual_weighted: bool,
+    input_path: String,
+    /// 直接读取带索引的 BAM，代替 mpileup 文本
+    bam: Option<String>,
+    /// BAM 模式下的位点列表
+    sites_path: String,
+    min_mapq: u8,
+    /// 测序错误模型（每碱基错误率与过度离散度）
+    error_model: ErrorModel,
+    /// 错误模型 p 值上限；给出时按显著性而不是固定计数阈值选 poc 位点
+    max_p: Option<f64>,
+    /// 丢弃非正常配对的读段（samtools mpileup 不加 `-A` 时的行为）
+    skip_orphans: bool,
+    /// BAM 模式下的并行区段数，0 为自动
+    segments: usize,
+    /// BAM 模式下每个位置最多计入的读段数（samtools mpileup `-d`），0 为不限
+    max_depth: usize,
+    /// 先统计深度分布，再据此选择错误模型（命令行显式给出的参数优先）
+    auto: bool,
+    error_rate_given: bool,
+    dispersion_given: bool,
+    /// `--auto` 时记录深度分布与所选参数
+    report_path: String,
+}
+
+impl Default for Opts {
+    fn default() -> Self {
+        Opts {
+            min_threshold: 0,
+            min_sb_p: 0.0,
+            keep_single_strand: false,
+            min_bq: None,
+            qual_weighted: false,
+            input_path: "./output/mp-samtool".to_string(),
+            bam: None,
+            sites_path: "./DB/2k.add".to_string(),
+            min_mapq: 20,
+            skip_orphans: false,
+            segments: 0,
+            max_depth: 8000,
+            error_model: ErrorModel { error_rate: 0.01, dispersion: 0.0 },
+            max_p: None,
+            auto: false,
+            error_rate_given: false,
+            dispersion_given: false,
+            report_path: "./output/run-report.txt".to_string(),
+        }
+    }
+}
+
+fn parse_args() -> Opts {
+    let mut opts = Opts::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--min-sb-p" => {
+                opts.min_sb_p = args.next().and_then(|s| s.parse().ok()).unwrap_or(0.0);
+            }
+            "--keep-single-strand" => opts.keep_single_strand = true,
+            "--min-bq" => opts.min_bq = args.next().and_then(|s| s.parse().ok()),
+            "--qual-weighted" => opts.qual_weighted = true,
+            "--input" => {
+                if let Some(path) = args.next() {
+                    opts.input_path = path;
+                }
+            }
+            "--bam" => opts.bam = args.next(),
+            "--sites" => {
+                if let Some(path) = args.next() {
+                    opts.sites_path = path;
+                }
+            }
+            "--min-mapq" => {
+                opts.min_mapq = args.next().and_then(|s| s.parse().ok()).unwrap_or(20);
+            }
+            "--skip-orphans" => opts.skip_orphans = true,
+            "--error-rate" => {
+                if let Some(e) = args.next().and_then(|s| s.parse().ok()) {
+                    opts.error_model.error_rate = e;
+                    opts.error_rate_given = true;
+                }
+            }
+            "--dispersion" => {
+                if let Some(rho) = args.next().and_then(|s| s.parse().ok()) {
+                    opts.error_model.dispersion = rho;
+                    opts.dispersion_given = true;
+                }
+            }
+            "--max-p" => opts.max_p = args.next().and_then(|s| s.parse().ok()),
+            "--auto" => opts.auto = true,
+            "--report" => {
+                if let Some(path) = args.next() {
+                    opts.report_path = path;
+                }
+            }
+            "--segments" => opts.segments = args.next().and_then(|s| s.parse().ok()).unwrap_or(0),
+            "-d" | "--max-depth" => {
+                opts.max_depth = args.next().and_then(|s| s.parse().ok()).unwrap_or(8000);
+            }
+            // 只有纯数字才是最小计数阈值，拼错的参数不会把阈值悄悄改成 0
+            _ => match arg.parse() {
+                Ok(n) => opts.min_threshold = n,
+                Err(_) => eprintln!("忽略未知参数: {}", arg),
+            },
+        }
+    }
+    if opts.min_bq.is_none() {
+        opts.min_bq = Some(if opts.bam.is_some() { 20 } else { 0 });
+    }
+    opts
+}
+
+/// 单个位点的处理结果
+///
+/// 错误模型 p 值依赖 `--auto` 统计出的参数，写出时才计算；输出行在它前后分成两段
+struct Site {
+    /// 错误模型 p 值之前的各列
+    head: String,
+    /// 之后的各列（`--qual-weighted` 的质量加权计数，含前导制表符），否则为空
+    tail: String,
+    min_cnt: usize,
+    multi: bool,
+    /// 所有次要等位在正反链上都有读段
+    both_strands: bool,
+    /// 主/次等位 × 正/反链 的 Fisher 精确检验 p 值
+    sb_p: f64,
+    /// 计入的总深度与主等位读段数
+    depth: usize,
+    major: usize,
+}
+
+impl Site {
+    /// 暂存为一行：min_cnt、multi、both_strands、sb_p、depth、major，之后是输出行的两段
+    fn spool(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+            self.min_cnt,
+            self.multi as u8,
+            self.both_strands as u8,
+            self.sb_p,
+            self.depth,
+            self.major,
+            self.head,
+            self.tail
+        )
+    }
+
+    /// 读回 [`Site::spool`] 写的一行；`head` 固定 7 列，其后（含前导制表符）为 `tail`
+    fn unspool(line: &str) -> Option<Site> {
+        let mut f = line.splitn(7, '\t');
+        let min_cnt = f.next()?.parse().ok()?;
+        let multi = f.next()? == "1";
+        let both_strands = f.next()? == "1";
+        let sb_p = f.next()?.parse().ok()?;
+        let depth = f.next()?.parse().ok()?;
+        let major = f.next()?.parse().ok()?;
+        let rest = f.next()?;
+        let split = rest.match_indices('\t').nth(6).map_or(rest.len(), |(i, _)| i);
+        let (head, tail) = rest.split_at(split);
+        Some(Site {
+            head: head.to_string(),
+            tail: tail.to_string(),
+            min_cnt,
+            multi,
+            both_strands,
+            sb_p,
+            depth,
+            major,
+        })
+    }
+
+    /// 最弱等位的读段全部来自测序错误的 p 值；单等位位点没有需要检验的次要等位
+    fn err_p(&self, model: &ErrorModel) -> f64 {
+        if self.multi { model.p_value(self.min_cnt, self.depth) } else { 1.0 }
+    }
+}
+
+/// 等位的固定输出顺序：A、C、G、T、N，其余碱基符号按字符排序，
+/// 之后是插入、缺失（各自按序列排序）
+fn allele_order(a: &Allele) -> (u8, &[u8]) {
+    match a {
+        Allele::Base(b) => {
+            let rank = match b {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                b'N' => 4,
+                _ => 5,
+            };
+            (rank, std::slice::from_ref(b))
+        }
+        Allele::Ins(seq) => (6, seq),
+        Allele::Del(seq) => (7, seq),
+    }
+}
+
+/// 三个输出文件，按输入顺序逐个位点写入
+struct Outputs<'a> {
+    stats: BufWriter<File>,
+    filter: BufWriter<File>,
+    poc: BufWriter<File>,
+    opts: &'a Opts,
+}
+
+impl Outputs<'_> {
+    fn write(&mut self, site: &Site) -> std::io::Result<()> {
+        let err_p = site.err_p(&self.opts.error_model);
+        let line = format!("{}\t{:.3e}{}", site.head, err_p, site.tail);
+        self.stats.write_all(line.as_bytes())?;
+        self.stats.write_all(b"\n")?;
+        if site.multi {
+            self.filter.write_all(line.as_bytes())?;
+            self.filter.write_all(b"\n")?;
+            // 次要等位只在单链出现、或链偏倚显著的位点多为假阳性
+            let strand_ok = (self.opts.keep_single_strand || site.both_strands)
+                && site.sb_p >= self.opts.min_sb_p;
+            let supported = match self.opts.max_p {
+                Some(alpha) => err_p <= alpha,
+                None => site.min_cnt > self.opts.min_threshold,
+            };
+            if supported && strand_ok {
+                let first = line.split('\t').next().unwrap_or("");
+                self.poc.write_all(first.as_bytes())?;
+                self.poc.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stats.flush()?;
+        self.filter.flush()?;
+        self.poc.flush()
+    }
+}
+
+/// 处理单行 mpileup 文本，输入格式见 [`Row`]
+fn process_line(line: &str, opts: &Opts) -> Option<Site> {
+    let row = Row::parse(line)?;
+    tally(&row.key(), row.observations(), opts)
+}
+
+/// 统计一个位点的读段观测
+///
+/// 输出列：位置、计数、min_count、等位字母、总深度、正/反链计数、
+/// 链偏倚 p 值、错误模型 p 值，以及（`--qual-weighted` 时）质量加权计数
+fn tally(
+    col1: &str,
+    observations: impl IntoIterator<Item = (Obs, Option<u8>)>,
+    opts: &Opts,
+) -> Option<Site> {
+    let min_bq = opts.min_bq.unwrap_or(0);
 
-    let mut cnt = HashMap::with_capacity(32);
-    for c in _col2.chars() {
-        *cnt.entry(c.to_ascii_uppercase()).or_insert(0) += 1;
+    // 每个等位的 [正链, 反链] 计数，及按 1 - 10^(-Q/10) 加权的计数
+    let mut cnt: HashMap<Allele, [usize; 2]> = HashMap::with_capacity(32);
+    let mut weighted: HashMap<Allele, f64> = HashMap::with_capacity(32);
+    for (obs, q) in observations {
+        if q.is_some_and(|q| q < min_bq) {
+            continue;
+        }
+        if opts.qual_weighted {
+            let w = q.map_or(1.0, |q| 1.0 - 10f64.powf(-(q as f64) / 10.0));
+            *weighted.entry(obs.allele.clone()).or_insert(0.0) += w;
+        }
+        cnt.entry(obs.allele).or_insert([0, 0])[obs.reverse as usize] += 1;
     }
+    cnt.remove(&Allele::Base(b'*'));
 
-    // 仅含 * 的行
-    if cnt.keys().all(|&c| c == '*') {
-        let out = format!("{}\t0\t0\t0\t0", col1);  // 只保留第一列
-        return Some((out, 0));
+    // 仅含 * 的行（或无覆盖）
+    if cnt.is_empty() {
+        let head = format!("{}\t0\t0\t0\t0\t0\t{:.3e}", col1, 1.0);
+        let tail = if opts.qual_weighted { "\t0".to_string() } else { String::new() };
+        return Some(Site {
+            head,
+            tail,
+            min_cnt: 0,
+            multi: false,
+            both_strands: true,
+            sb_p: 1.0,
+            depth: 0,
+            major: 0,
+        });
     }
 
-    let min_cnt = cnt
-        .iter()
-        .filter(|&(&c, _)| c != '*')
-        .map(|(_, &v)| v)
-        .min()?;
+    let min_cnt = cnt.values().map(|s| s[0] + s[1]).min()?;
+
+    // 固定等位顺序，保证同样输入得到逐字节相同的输出
+    let mut alleles: Vec<(Allele, [usize; 2])> = cnt.into_iter().collect();
+    alleles.sort_unstable_by(|a, b| allele_order(&a.0).cmp(&allele_order(&b.0)));
 
     // 预分配字符串，手动拼接
     let mut count_str = String::with_capacity(256);
-    let mut letters: Vec<char> = Vec::with_capacity(32);
+    let mut strand_str = String::with_capacity(256);
+    let mut weighted_str = String::with_capacity(256);
+    // 等位字母直接拼接；插入/缺失以 +/- 开头且排在碱基之后，仍可无歧义拆分
+    let mut letters_str = String::with_capacity(32);
     let mut total = 0usize;
 
     let mut first = true;
-    for (&c, &v) in cnt.iter() {
-        if c == '*' {
-            continue;
-        }
+    for (a, [fwd, rev]) in &alleles {
+        let c = a.to_string();
         if !first {
             count_str.push(',');
+            strand_str.push(',');
+            weighted_str.push(',');
         }
         first = false;
-        count_str.push(c);
+        count_str.push_str(&c);
         count_str.push(':');
-        count_str.push_str(&v.to_string());
-        letters.push(c);
-        total += v;
-    }
-    letters.sort_unstable();
-    let letters_str: String = letters.into_iter().collect();
-
-    // 修改输出格式，跳过第二列
-    let out = if col3.is_empty() {
-        format!(
-            "{}\t{}\t{}\t{}\t{}",
-            col1, count_str, min_cnt, letters_str, total
+        count_str.push_str(&(fwd + rev).to_string());
+        strand_str.push_str(&c);
+        strand_str.push(':');
+        strand_str.push_str(&format!("{}/{}", fwd, rev));
+        if opts.qual_weighted {
+            weighted_str.push_str(&format!("{}:{:.2}", c, weighted[a]));
+        }
+        letters_str.push_str(&c);
+        total += fwd + rev;
+    }
+    let multi = alleles.len() > 1;
+
+    // 按总数降序（同数按固定等位顺序）排出主、次等位
+    let mut ranked = alleles;
+    ranked.sort_by_key(|(_, s)| std::cmp::Reverse(s[0] + s[1]));
+    let both_strands = ranked.iter().skip(1).all(|(_, s)| s[0] > 0 && s[1] > 0);
+    let major = ranked[0].1[0] + ranked[0].1[1];
+    let sb_p = match ranked.as_slice() {
+        [(_, major), (_, minor), ..] => fisher_exact(major[0], major[1], minor[0], minor[1]),
+        _ => 1.0,
+    };
+
+    let head = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{:.3e}",
+        col1, count_str, min_cnt, letters_str, total, strand_str, sb_p
+    );
+    let tail = if opts.qual_weighted { format!("\t{}", weighted_str) } else { String::new() };
+    Some(Site { head, tail, min_cnt, multi, both_strands, sb_p, depth: total, major })
+}
+
+/// 按输入顺序对每个位点调用 `f`：BAM 模式直接 pileup，否则分块读取 mpileup 文本
+fn for_each_site<F>(opts: &Opts, mut f: F) -> std::io::Result<()>
+where
+    F: FnMut(Site) -> std::io::Result<()>,
+{
+    if let Some(bam_path) = &opts.bam {
+        // 直接从 BAM 做 pileup（-q/-Q/-A/-d 与原 samtools 参数一致）
+        let popts = PileupOpts {
+            min_mapq: opts.min_mapq,
+            count_orphans: !opts.skip_orphans,
+            segments: opts.segments,
+            max_depth: opts.max_depth,
+        };
+        pileup::run(
+            bam_path,
+            &opts.sites_path,
+            &popts,
+            |key, obs| tally(key, obs.iter().cloned(), opts),
+            |site| match site {
+                Some(site) => f(site),
+                None => Ok(()),
+            },
         )
     } else {
-        format!(
-            "{}\t{}\t{}\t{}\t{}\t{}",
-            col1, col3, count_str, min_cnt, letters_str, total
-        )
+        // 内存映射 + 跳过 UTF-8 校验
+        let file = File::open(&opts.input_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let content = unsafe { std::str::from_utf8_unchecked(&mmap) };
+
+        // 分块并行处理，块内保持原始顺序，处理完立即写出
+        let mut lines = content.lines();
+        let mut chunk: Vec<&str> = Vec::with_capacity(CHUNK_LINES);
+        loop {
+            chunk.clear();
+            chunk.extend(lines.by_ref().take(CHUNK_LINES));
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            let sites: Vec<Option<Site>> =
+                chunk.par_iter().map(|line| process_line(line, opts)).collect();
+            for site in sites.into_iter().flatten() {
+                f(site)?;
+            }
+        }
+    }
+}
+
+/// 统计深度分布并选择错误模型，结果写入运行报告
+///
+/// 代替 jacky14.sh 中只看 `samtools depth` 前十行、以深度 100 为界切换参数的做法。
+/// 文本模式之后再读一遍 mpileup；BAM 模式的 pileup 只做一次，位点暂存到 [`SPOOL_PATH`]
+/// 待参数确定后读回写出（返回该路径），内存占用不随位点数增长
+fn auto_configure(opts: &mut Opts) -> std::io::Result<Option<&'static str>> {
+    let mut profile = DepthProfile::default();
+    let mut spool = match opts.bam {
+        Some(_) => Some(BufWriter::new(File::create(SPOOL_PATH)?)),
+        None => None,
     };
-    Some((out, min_cnt))
+    for_each_site(opts, |site| {
+        profile.add(site.depth, site.major);
+        match spool.as_mut() {
+            Some(w) => site.spool(w),
+            None => Ok(()),
+        }
+    })?;
+    if let Some(mut w) = spool {
+        w.flush()?;
+    }
+    let summary = profile.summary();
+    let model = profile.choose_model(&summary);
+    if !opts.error_rate_given {
+        opts.error_model.error_rate = model.error_rate;
+    }
+    if !opts.dispersion_given {
+        opts.error_model.dispersion = model.dispersion;
+    }
+    let alpha = *opts.max_p.get_or_insert(DEFAULT_MAX_P);
+    depth::write_report(&opts.report_path, &summary, &opts.error_model, alpha)?;
+    println!(
+        "深度中位数 = {:.1}，覆盖度 = {:.4}，运行报告已保存到 '{}'",
+        summary.median, summary.breadth, opts.report_path
+    );
+    Ok(opts.bam.as_ref().map(|_| SPOOL_PATH))
 }
 
 fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let min_threshold = args.get(1).and_then(|s| s.parse::<usize>().ok()).unwrap_or(0);
+    let mut opts = parse_args();
+    let spool = if opts.auto { auto_configure(&mut opts)? } else { None };
+    let min_threshold = opts.min_threshold;
 
-    let input_path  = "./output/2lie-mp-samtool";
     let stats_path  = "all-stats.txt";
     let filter_path = "filter-result-with-counts.txt";
     let poc_path    = "./output/poc.txt";
 
-    // 内存映射 + 跳过 UTF-8 校验
-    let file = File::open(input_path)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-    let content = unsafe { std::str::from_utf8_unchecked(&mmap) };
-
-    // 并行处理
-    let results: Arc<DashMap<usize, (String, usize, bool)>> = Arc::new(DashMap::new());
-    content
-        .lines()
-        .collect::<Vec<&str>>()
-        .par_iter()
-        .enumerate()
-        .for_each(|(idx, &line)| {
-            if let Some((processed, min_cnt)) = process_line(line) {
-                // 重新统计字母，确定是否多字母组合
-                let mut cnt = HashMap::with_capacity(32);
-                if let Some(col) = line.split('\t').nth(1) {
-                    for c in col.chars() {
-                        *cnt.entry(c.to_ascii_uppercase()).or_insert(0) += 1;
-                    }
-                }
-                let multi = cnt
-                    .keys()
-                    .filter(|&&c| c != '*')
-                    .collect::<std::collections::HashSet<_>>()
-                    .len()
-                    > 1;
-                results.insert(idx, (processed, min_cnt, multi));
-            }
-        });
-
     // 8 MiB 缓冲写文件
     const BUF_CAP: usize = 8 * 1024 * 1024;
-    let mut stats  = BufWriter::with_capacity(BUF_CAP, File::create(stats_path)?);
-    let mut filter = BufWriter::with_capacity(BUF_CAP, File::create(filter_path)?);
-    let mut poc    = BufWriter::with_capacity(BUF_CAP, File::create(poc_path)?);
-
-    // 按原始顺序输出
-    let mut sorted: Vec<_> = results.iter().collect();
-    sorted.sort_by_key(|e| *e.key());
-
-    for entry in sorted {
-        let (line, min_cnt, multi) = entry.value();
-        stats.write_all(line.as_bytes())?;
-        stats.write_all(b"\n")?;
-        if *multi {
-            filter.write_all(line.as_bytes())?;
-            filter.write_all(b"\n")?;
-            if *min_cnt > min_threshold {
-                let first = line.split('\t').next().unwrap_or("");
-                poc.write_all(first.as_bytes())?;
-                poc.write_all(b"\n")?;
+    let mut out = Outputs {
+        stats:  BufWriter::with_capacity(BUF_CAP, File::create(stats_path)?),
+        filter: BufWriter::with_capacity(BUF_CAP, File::create(filter_path)?),
+        poc:    BufWriter::with_capacity(BUF_CAP, File::create(poc_path)?),
+        opts:   &opts,
+    };
+
+    match spool {
+        Some(path) => {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                let site = Site::unspool(&line).ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("'{}' 格式错误: {}", path, line))
+                })?;
+                out.write(&site)?;
             }
+            fs::remove_file(path)?;
         }
+        None => for_each_site(&opts, |site| out.write(&site))?,
     }
+    out.flush()?;
 
     println!("完整统计已保存到 '{}'", stats_path);
     println!("过滤结果已保存到 '{}'", filter_path);
-    println!("POC 列表已保存到 '{}'，阈值 = {}", poc_path, min_threshold);
+    match opts.max_p {
+        Some(alpha) => println!(
+            "POC 列表已保存到 '{}'，错误率 = {}，p <= {}",
+            poc_path, opts.error_model.error_rate, alpha
+        ),
+        None => println!("POC 列表已保存到 '{}'，阈值 = {}", poc_path, min_threshold),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+
+    /// 逐位点的统计行（错误模型 p 值只由计数决定，不参与比较）
+    fn stats_lines(opts: &Opts) -> Vec<String> {
+        let mut lines = Vec::new();
+        for_each_site(opts, |site| {
+            lines.push(site.head + &site.tail);
+            Ok(())
+        })
+        .unwrap();
+        lines
+    }
+
+    #[test]
+    fn spooled_sites_round_trip() {
+        let opts = Opts { qual_weighted: true, input_path: format!("{}/pileup.mpileup", DATA), ..Opts::default() };
+        let mut buf = Vec::new();
+        let mut sites = Vec::new();
+        for_each_site(&opts, |site| {
+            site.spool(&mut buf)?;
+            sites.push(site);
+            Ok(())
+        })
+        .unwrap();
+        let mut back: Vec<Site> = std::str::from_utf8(&buf).unwrap().lines().map(|l| Site::unspool(l).unwrap()).collect();
+        assert_eq!(back.len(), sites.len());
+        for (a, b) in sites.iter().zip(&back) {
+            assert_eq!((&a.head, &a.tail, a.min_cnt, a.multi), (&b.head, &b.tail, b.min_cnt, b.multi));
+            assert_eq!((a.both_strands, a.sb_p, a.depth, a.major), (b.both_strands, b.sb_p, b.depth, b.major));
+        }
+        assert!(back.iter().all(|s| s.tail.starts_with('\t')));
+
+        // p 值按最短可还原的写法暂存，读回后不变
+        let site = Site { sb_p: 1.234_567_890_123e-17, tail: String::new(), ..back.swap_remove(1) };
+        let mut buf = Vec::new();
+        site.spool(&mut buf).unwrap();
+        let again = Site::unspool(std::str::from_utf8(&buf).unwrap().trim_end()).unwrap();
+        assert_eq!((again.sb_p, again.head, again.tail), (site.sb_p, site.head, site.tail));
+    }
+
+    #[test]
+    fn bam_path_matches_mpileup_text() {
+        // 夹具说明见 tests/data/mk_pileup_bam.py；pileup.mpileup 为同一批读段的 mpileup 输出
+        // （mk_pileup_mpileup.sh 用 samtools 生成）
+        let text = Opts {
+            qual_weighted: true,
+            input_path: format!("{}/pileup.mpileup", DATA),
+            ..Opts::default()
+        };
+        let bam = Opts {
+            bam: Some(format!("{}/pileup.bam", DATA)),
+            sites_path: format!("{}/sites.txt", DATA),
+            // 切成多个区段，分别按索引查询
+            segments: 4,
+            qual_weighted: true,
+            ..Opts::default()
+        };
+        let from_bam = stats_lines(&bam);
+        let from_text = stats_lines(&text);
+        let key = |l: &String| l.split('\t').next().unwrap().to_string();
+        let by_site: HashMap<String, &String> = from_text.iter().map(|l| (key(l), l)).collect();
+        // samtools 不输出 BAM 头部里没有的序列上的位点，BAM 模式按无覆盖输出
+        for line in &from_bam {
+            match by_site.get(&key(line)) {
+                Some(text) => assert_eq!(&line, text, "{}", key(line)),
+                None => assert_eq!((key(line).as_str(), line.split('\t').nth(4)), ("chrX:5", Some("0"))),
+            }
+        }
+
+        // 位点保持列表中的顺序和重复（文本模式为 BAM 头部顺序），缺失序列与无覆盖位点也输出；
+        // 插入/缺失锚定在前一个碱基上
+        let listed: Vec<String> =
+            site_id::read_list(&bam.sites_path).unwrap().iter().map(|s| s.to_string()).collect();
+        assert_eq!(from_bam.iter().map(key).collect::<Vec<_>>(), listed);
+        assert_eq!(from_text.len() + 2, from_bam.len());
+        assert!(from_bam[1].contains("-A:1"));
+        assert!(from_bam[3].contains("+CC:1"));
+        assert!(from_bam[8].contains("-TT:1"));
+    }
+}
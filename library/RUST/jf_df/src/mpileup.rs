@@ -0,0 +1,235 @@
+// src/mpileup.rs
+//! samtools mpileup 第 5 列（read bases）解析
+
+use std::fmt;
+
+use site_id::SiteId;
+
+/// 读段在位点上的等位：碱基，或锚定在该位点之后的插入/缺失
+///
+/// 输出写作 `A`、`+AT`（其后插入 AT）、`-G`（其后缺失 G）；
+/// 缺失占位 `*` 记为 `Base(b'*')`，不算等位
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Allele {
+    Base(u8),
+    Ins(Box<[u8]>),
+    Del(Box<[u8]>),
+}
+
+impl fmt::Display for Allele {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sign, seq): (&str, &[u8]) = match self {
+            Allele::Base(b) => ("", std::slice::from_ref(b)),
+            Allele::Ins(seq) => ("+", seq),
+            Allele::Del(seq) => ("-", seq),
+        };
+        f.write_str(sign)?;
+        f.write_str(&String::from_utf8_lossy(seq))
+    }
+}
+
+/// 单个读段观测：等位（已转大写）与所在链
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Obs {
+    pub allele: Allele,
+    pub reverse: bool,
+}
+
+/// 逐个返回 mpileup 碱基串中真实的读段观测
+///
+/// - `^` 及其后的比对质量字节、`$` 直接跳过
+/// - `.` / `,` 解析为参考碱基
+/// - 紧跟在碱基后的 `+2AC` / `-1G` 属于同一读段，该读段的等位记为插入/缺失
+/// - 删除占位 `*`/`#` 与参考跳过 `>`/`<` 统一返回 `*`
+/// - 大写/`.`/`*`/`>` 为正链，小写/`,`/`#`/`<` 为反链
+pub struct Bases<'a> {
+    s: &'a [u8],
+    i: usize,
+    ref_base: u8,
+}
+
+impl<'a> Bases<'a> {
+    pub fn new(s: &'a [u8], ref_base: u8) -> Self {
+        Bases { s, i: 0, ref_base: ref_base.to_ascii_uppercase() }
+    }
+
+    /// 当前位置是否为 `+`/`-` 加长度前缀
+    fn at_indel(&self) -> bool {
+        matches!(self.s.get(self.i), Some(b'+' | b'-'))
+            && self.s.get(self.i + 1).is_some_and(u8::is_ascii_digit)
+    }
+
+    /// 读取一段插入/缺失：符号、长度前缀，再取对应数量的碱基
+    fn indel(&mut self) -> Allele {
+        let sign = self.s[self.i];
+        self.i += 1;
+        let mut n = 0usize;
+        while let Some(d) = self.s.get(self.i).filter(|d| d.is_ascii_digit()) {
+            n = n * 10 + (d - b'0') as usize;
+            self.i += 1;
+        }
+        let end = (self.i + n).min(self.s.len());
+        let seq: Box<[u8]> = self.s[self.i..end].to_ascii_uppercase().into();
+        self.i = end;
+        if sign == b'+' {
+            Allele::Ins(seq)
+        } else {
+            Allele::Del(seq)
+        }
+    }
+}
+
+impl Iterator for Bases<'_> {
+    type Item = Obs;
+
+    fn next(&mut self) -> Option<Obs> {
+        while self.i < self.s.len() {
+            if self.at_indel() {
+                // 没有锚定碱基的插入/缺失（不应出现）整段跳过
+                self.indel();
+                continue;
+            }
+            let c = self.s[self.i];
+            self.i += 1;
+            let (base, reverse) = match c {
+                // 读段起始：下一个字节是比对质量
+                b'^' => {
+                    self.i += 1;
+                    continue;
+                }
+                // 读段结束
+                b'$' => continue,
+                b'.' => (self.ref_base, false),
+                b',' => (self.ref_base, true),
+                b'*' | b'>' => (b'*', false),
+                b'#' | b'<' => (b'*', true),
+                _ => (c.to_ascii_uppercase(), c.is_ascii_lowercase()),
+            };
+            let allele = if self.at_indel() { self.indel() } else { Allele::Base(base) };
+            return Some(Obs { allele, reverse });
+        }
+        None
+    }
+}
+
+/// 一行 pileup 输入
+///
+/// 支持两种布局：
+/// - 完整六列 mpileup：chrom、pos、ref、depth、bases、quals
+/// - 旧的精简格式：site、bases、ref（可选），site 为 `contig:pos` 或 `pos`
+pub struct Row<'a> {
+    contig: Option<&'a str>,
+    site: &'a str,
+    pub ref_base: u8,
+    pub bases: &'a [u8],
+    /// Phred+33 碱基质量，与读段观测一一对应
+    pub quals: Option<&'a [u8]>,
+}
+
+impl<'a> Row<'a> {
+    pub fn parse(line: &'a str) -> Option<Self> {
+        // 只去掉行尾换行：六列行的碱基、质量列可能为空，不能连同制表符一起去掉
+        let cols: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let first_byte = |s: &str| s.bytes().next().unwrap_or(b'N');
+        if cols.len() >= 6 {
+            Some(Row {
+                contig: Some(cols[0]),
+                site: cols[1],
+                ref_base: first_byte(cols[2]),
+                bases: cols[4].as_bytes(),
+                quals: Some(cols[5].as_bytes()),
+            })
+        } else if cols.len() >= 2 {
+            Some(Row {
+                contig: None,
+                site: cols[0],
+                ref_base: cols.get(2).map_or(b'N', |s| first_byte(s)),
+                bases: cols[1].as_bytes(),
+                quals: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// 位点标识（输出第一列），格式见 [`SiteId`]
+    pub fn key(&self) -> String {
+        match (self.contig, self.site.parse::<u64>()) {
+            (Some(contig), Ok(pos)) => SiteId::new(contig, pos).to_string(),
+            _ => self.site.to_string(),
+        }
+    }
+
+    /// 读段观测及其碱基质量（无质量列时为 `None`）
+    pub fn observations(&self) -> impl Iterator<Item = (Obs, Option<u8>)> + '_ {
+        let mut quals = self.quals.map(|q| q.iter());
+        Bases::new(self.bases, self.ref_base).map(move |obs| {
+            let q = quals
+                .as_mut()
+                .and_then(|it| it.next())
+                .map(|&b| b.saturating_sub(33));
+            (obs, q)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 `(等位, 是否反链)` 列出碱基串中的读段观测
+    fn tokens(bases: &str, ref_base: u8) -> Vec<(String, bool)> {
+        Bases::new(bases.as_bytes(), ref_base)
+            .map(|obs| (obs.allele.to_string(), obs.reverse))
+            .collect()
+    }
+
+    fn tok(allele: &str, reverse: bool) -> (String, bool) {
+        (allele.to_string(), reverse)
+    }
+
+    #[test]
+    fn read_start_and_end_markers() {
+        // ^ 后的比对质量字节即使是 + 或 . 也不当作碱基或插入
+        assert_eq!(tokens("^I.$", b'a'), [tok("A", false)]);
+        assert_eq!(tokens("^+,^.T$", b'C'), [tok("C", true), tok("T", false)]);
+    }
+
+    #[test]
+    fn indels_belong_to_the_anchor_read() {
+        assert_eq!(tokens(",+2ac", b'G'), [tok("+AC", true)]);
+        assert_eq!(tokens(".-1G", b'A'), [tok("-G", false)]);
+        // 长度前缀多位数，且后面紧跟下一个读段
+        assert_eq!(tokens("T+12ACGTACGTACGTc", b'A'), [tok("+ACGTACGTACGT", false), tok("C", true)]);
+        // 没有锚定碱基的整段跳过
+        assert_eq!(tokens("-1G.", b'A'), [tok("A", false)]);
+    }
+
+    #[test]
+    fn deletion_placeholders_and_ref_skips() {
+        assert_eq!(
+            tokens("*#><", b'A'),
+            [tok("*", false), tok("*", true), tok("*", false), tok("*", true)]
+        );
+    }
+
+    #[test]
+    fn qualities_follow_observations() {
+        let row = Row::parse("chr1\t5\tA\t3\t^I.$,+2ac*\tI5#").unwrap();
+        let quals: Vec<Option<u8>> = row.observations().map(|(_, q)| q).collect();
+        assert_eq!(quals, [Some(40), Some(20), Some(2)]);
+    }
+
+    #[test]
+    fn row_keeps_empty_trailing_columns() {
+        let row = Row::parse("chr1\t100\tA\t0\t\t\r\n").unwrap();
+        assert_eq!(row.key(), "chr1:100");
+        assert_eq!(row.ref_base, b'A');
+        assert!(row.bases.is_empty());
+        assert_eq!(row.observations().count(), 0);
+
+        let legacy = Row::parse("chr1:100\t..,A\tA").unwrap();
+        assert_eq!(legacy.key(), "chr1:100");
+        assert_eq!(legacy.observations().count(), 4);
+    }
+}
@@ -0,0 +1,361 @@
+// src/pileup.rs
+//! 直接从带索引的 BAM 生成位点观测，替代 sambamba slice + samtools mpileup
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+
+use ahash::AHashMap as HashMap;
+use rayon::prelude::*;
+
+use crate::bam::{self, IndexedReader, Record};
+use crate::mpileup::{Allele, Obs};
+use site_id::SiteId;
+
+/// 读段过滤规则（对应 `samtools mpileup -q/-A`）与并行切分方式
+pub struct PileupOpts {
+    pub min_mapq: u8,
+    /// 计入非正常配对的读段（`-A`）
+    pub count_orphans: bool,
+    /// 全基因组切成的区段数（对应 MOST-2.sh 的段数），0 为按线程数自动
+    pub segments: usize,
+    /// 每个位置最多计入的读段数（对应 `samtools mpileup -d`，缺省 8000），0 为不限
+    pub max_depth: usize,
+}
+
+/// 读段数上限：按起点顺序读入读段，起点处已计入的读段达到上限时丢弃新读段
+///
+/// 与 samtools 一样在过滤之后、重叠配对合并之前计数。区段从第一个位点开始查询，
+/// 起点在此之前、又没覆盖到任何位点的读段不计，区段开头的上限可能比 samtools 略宽
+struct DepthCap {
+    max: usize,
+    /// 已计入读段的参考终点（0-based 半开）
+    ends: BinaryHeap<Reverse<i64>>,
+}
+
+impl DepthCap {
+    fn new(max: usize) -> Self {
+        DepthCap { max, ends: BinaryHeap::new() }
+    }
+
+    /// 起点为 `start`、终点为 `end` 的读段是否计入
+    fn admit(&mut self, start: i64, end: i64) -> bool {
+        if self.max == 0 {
+            return true;
+        }
+        while self.ends.peek().is_some_and(|&Reverse(e)| e <= start) {
+            self.ends.pop();
+        }
+        if self.ends.len() >= self.max {
+            return false;
+        }
+        self.ends.push(Reverse(end));
+        true
+    }
+}
+
+/// 一个并行处理单元：位点列表中同一条参考序列上连续、位置不减的一段位点
+struct Region {
+    /// BAM 中找不到该序列时为 None，位点按无覆盖输出
+    tid: Option<usize>,
+    /// 位点列表里写的序列名（只有位置的旧列表为空）
+    contig: String,
+    /// 1-based，不减（列表中重复的位点各输出一次）
+    positions: Vec<u64>,
+}
+
+/// 位点上的一条读段观测
+struct PileObs {
+    obs: Obs,
+    qual: Option<u8>,
+    /// 配对读段编号，非配对为 u32::MAX
+    pair: u32,
+}
+
+/// 按 BAM 头部的序列长度把位点分到各区段
+///
+/// 所有序列总长按 `segments` 等分得到窗口长度，每条序列再按自身长度切窗，
+/// 不再依赖写死的基因组长度和序列名。位点保持列表（`2k.add`）中的顺序和重复：
+/// 下游 RefBuild / jf_score 按这个顺序与数据库逐位对应，换序列、位置变小或跨窗时另起一段。
+/// 只有位置的旧列表对应 BAM 的第一条参考序列
+fn build_regions(header: &bam::Header, sites: Vec<SiteId>, segments: usize) -> Vec<Region> {
+    let total: u64 = header.lengths.iter().sum();
+    let window = total.div_ceil(segments.max(1) as u64).max(1);
+
+    let mut missing: HashMap<String, usize> = HashMap::new();
+    let mut out_of_range = 0usize;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut last_window = 0;
+    for site in sites {
+        let tid = if site.contig.is_empty() {
+            (!header.names.is_empty()).then_some(0)
+        } else {
+            let tid = header.tid(&site.contig);
+            if tid.is_none() {
+                *missing.entry(site.contig.clone()).or_insert(0) += 1;
+            }
+            tid
+        };
+        // 超出序列长度的位点同样按无覆盖处理
+        let tid = tid.filter(|&t| {
+            let ok = site.pos <= header.lengths[t];
+            out_of_range += !ok as usize;
+            ok
+        });
+        let win = (site.pos - 1) / window;
+
+        let same = regions.last().is_some_and(|r| {
+            r.contig == site.contig
+                && r.tid == tid
+                && r.positions.last().is_some_and(|&p| p <= site.pos)
+                && (tid.is_none() || win == last_window)
+        });
+        match regions.last_mut() {
+            Some(r) if same => r.positions.push(site.pos),
+            _ => regions.push(Region { tid, contig: site.contig, positions: vec![site.pos] }),
+        }
+        last_window = win;
+    }
+
+    let mut missing: Vec<(String, usize)> = missing.into_iter().collect();
+    missing.sort_unstable();
+    for (name, n) in &missing {
+        eprintln!("警告: 位点列表中的序列 '{}' 不在 BAM 头部（{} 个位点按无覆盖输出）", name, n);
+    }
+    if out_of_range > 0 {
+        eprintln!("警告: {} 个位点超出所在序列长度，按无覆盖输出", out_of_range);
+    }
+    regions
+}
+
+/// 把一条读段落在区段位点上的碱基加入 `piles`
+///
+/// 比对块最后一个碱基之后紧接插入/缺失时，该读段在这个位点上记为插入/缺失等位
+/// （与 mpileup 把 `+2AC`/`-1G` 写在锚定碱基后面一致）
+fn add_record(rec: &Record, positions: &[u64], pair: u32, piles: &mut [Vec<PileObs>]) {
+    let reverse = rec.flag & bam::FLAG_REVERSE != 0;
+    let mut rpos = rec.pos as u64; // 0-based
+    let mut qpos = 0usize;
+    for (k, &(op, len)) in rec.cigar.iter().enumerate() {
+        let len = len as u64;
+        match op {
+            bam::CIGAR_M | bam::CIGAR_EQ | bam::CIGAR_X => {
+                // 1-based 位点 p 落在 [rpos, rpos + len) 内即 rpos < p <= rpos + len
+                let lo = positions.partition_point(|&p| p <= rpos);
+                for (j, &p) in positions[lo..].iter().enumerate() {
+                    if p > rpos + len {
+                        break;
+                    }
+                    let i = qpos + (p - 1 - rpos) as usize;
+                    let (Some(&base), Some(&q)) = (rec.seq.get(i), rec.qual.get(i)) else {
+                        break;
+                    };
+                    let allele = match rec.cigar.get(k + 1) {
+                        Some(&(bam::CIGAR_I, n)) if p == rpos + len => {
+                            let ins = rec.seq.get(i + 1..i + 1 + n as usize).unwrap_or(&[]);
+                            Allele::Ins(ins.into())
+                        }
+                        Some(&(bam::CIGAR_D, n)) if p == rpos + len => {
+                            let offset = rpos + len - rec.pos as u64;
+                            Allele::Del(rec.deleted_bases(offset, n as u64).into())
+                        }
+                        _ => Allele::Base(base),
+                    };
+                    piles[lo + j].push(PileObs {
+                        obs: Obs { allele, reverse },
+                        qual: (q != 0xff).then_some(q),
+                        pair,
+                    });
+                }
+                rpos += len;
+                qpos += len as usize;
+            }
+            bam::CIGAR_I | bam::CIGAR_S => qpos += len as usize,
+            bam::CIGAR_D | bam::CIGAR_N => rpos += len,
+            _ => {}
+        }
+    }
+}
+
+/// 同一位点上重叠的两条配对读段只计一次，质量按 samtools 的规则调整：
+/// 碱基相同则质量相加（上限 200），不同则保留质量高者并乘 0.8
+fn resolve_overlaps(pile: &mut Vec<PileObs>) {
+    let mut idx: Vec<usize> = (0..pile.len()).filter(|&i| pile[i].pair != u32::MAX).collect();
+    if idx.len() < 2 {
+        return;
+    }
+    // 稳定排序：同一对读段保持读入顺序，结果可复现
+    idx.sort_by_key(|&i| pile[i].pair);
+
+    let mut dropped = vec![false; pile.len()];
+    for w in idx.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        if pile[a].pair != pile[b].pair || dropped[a] {
+            continue;
+        }
+        let qa = pile[a].qual.unwrap_or(0xff);
+        let qb = pile[b].qual.unwrap_or(0xff);
+        let (keep, drop, q) = if pile[a].obs.allele == pile[b].obs.allele {
+            (a, b, (qa as u16 + qb as u16).min(200) as u8)
+        } else if qa >= qb {
+            (a, b, (qa as f64 * 0.8) as u8)
+        } else {
+            (b, a, (qb as f64 * 0.8) as u8)
+        };
+        if pile[keep].qual.is_some() {
+            pile[keep].qual = Some(q);
+        }
+        dropped[drop] = true;
+    }
+    let mut k = 0;
+    pile.retain(|_| {
+        k += 1;
+        !dropped[k - 1]
+    });
+}
+
+fn pileup_region<T, F>(
+    reader: &mut IndexedReader,
+    region: &Region,
+    opts: &PileupOpts,
+    f: &F,
+) -> io::Result<Vec<T>>
+where
+    F: Fn(&str, &[(Obs, Option<u8>)]) -> T,
+{
+    let positions = &region.positions;
+    let mut piles: Vec<Vec<PileObs>> = (0..positions.len()).map(|_| Vec::new()).collect();
+
+    if let Some(tid) = region.tid {
+        let beg = positions[0] - 1;
+        let end = positions[positions.len() - 1];
+        let skip = bam::FLAG_UNMAPPED | bam::FLAG_SECONDARY | bam::FLAG_QCFAIL | bam::FLAG_DUP;
+        let mut pair_ids: HashMap<Vec<u8>, u32> = HashMap::new();
+        let mut cap = DepthCap::new(opts.max_depth);
+
+        reader.fetch(tid, beg, end, |rec| {
+            if rec.flag & skip != 0 || rec.mapq < opts.min_mapq || rec.seq.is_empty() {
+                return;
+            }
+            let paired = rec.flag & bam::FLAG_PAIRED != 0;
+            if paired && rec.flag & bam::FLAG_PROPER_PAIR == 0 && !opts.count_orphans {
+                return;
+            }
+            if !cap.admit(rec.pos, rec.end()) {
+                return;
+            }
+            let pair = if paired {
+                let next = pair_ids.len() as u32;
+                *pair_ids.entry(rec.name.clone()).or_insert(next)
+            } else {
+                u32::MAX
+            };
+            add_record(rec, positions, pair, &mut piles);
+        })?;
+    }
+
+    let mut obs = Vec::new();
+    Ok(positions
+        .iter()
+        .zip(piles.iter_mut())
+        .map(|(pos, pile)| {
+            resolve_overlaps(pile);
+            obs.clear();
+            obs.extend(pile.iter().map(|p| (p.obs.clone(), p.qual)));
+            f(&SiteId::new(&region.contig, *pos).to_string(), &obs)
+        })
+        .collect())
+}
+
+/// 按位点列表对 BAM 做 pileup，对每个位点调用 `f(位点标识, 观测)`，
+/// 结果按位点顺序依次交给 `sink`
+///
+/// 区段按批并行，每批处理完即写出，内存只与一批区段的大小有关
+pub fn run<T, F, W>(
+    bam_path: &str,
+    sites_path: &str,
+    opts: &PileupOpts,
+    f: F,
+    mut sink: W,
+) -> io::Result<()>
+where
+    T: Send,
+    F: Fn(&str, &[(Obs, Option<u8>)]) -> T + Sync,
+    W: FnMut(T) -> io::Result<()>,
+{
+    let header = IndexedReader::open(bam_path)?.header;
+    let threads = rayon::current_num_threads();
+    let segments = match opts.segments {
+        0 => threads * 4,
+        n => n,
+    };
+    let regions = build_regions(&header, site_id::read_list(sites_path)?, segments);
+
+    for batch in regions.chunks(threads.max(1) * 2) {
+        let chunks = batch
+            .par_iter()
+            .map_init(
+                || IndexedReader::open(bam_path),
+                |reader, region| match reader {
+                    Ok(reader) => pileup_region(reader, region, opts, &f),
+                    Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                },
+            )
+            .collect::<io::Result<Vec<Vec<T>>>>()?;
+        for item in chunks.into_iter().flatten() {
+            sink(item)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pile_obs(base: u8, qual: u8, pair: u32) -> PileObs {
+        PileObs { obs: Obs { allele: Allele::Base(base), reverse: false }, qual: Some(qual), pair }
+    }
+
+    #[test]
+    fn overlapping_mates_count_once() {
+        let mut pile = vec![
+            pile_obs(b'A', 30, 0),
+            pile_obs(b'G', 40, u32::MAX),
+            pile_obs(b'C', 20, 1),
+            pile_obs(b'A', 35, 0),
+            pile_obs(b'T', 30, 1),
+            pile_obs(b'G', 150, 2),
+            pile_obs(b'G', 100, 2),
+            pile_obs(b'G', 40, u32::MAX),
+        ];
+        resolve_overlaps(&mut pile);
+        let got: Vec<(u8, Option<u8>)> = pile
+            .iter()
+            .map(|p| match p.obs.allele {
+                Allele::Base(b) => (b, p.qual),
+                _ => unreachable!(),
+            })
+            .collect();
+        // 同碱基质量相加（上限 200），不同碱基留质量高者 × 0.8，非配对读段不动
+        assert_eq!(
+            got,
+            [(b'A', Some(65)), (b'G', Some(40)), (b'T', Some(24)), (b'G', Some(200)), (b'G', Some(40))]
+        );
+    }
+
+    #[test]
+    fn depth_cap_counts_reads_over_start() {
+        let mut cap = DepthCap::new(2);
+        // (起点, 终点) 按起点排序
+        let got: Vec<bool> = [(0, 10), (2, 5), (4, 8), (5, 12), (6, 9), (10, 20), (11, 13)]
+            .iter()
+            .map(|&(start, end)| cap.admit(start, end))
+            .collect();
+        // 4 处已有 2 条；5 处 (2, 5) 已结束；6 处又满 2 条；10 处 (0, 10) 结束，11 处又满
+        assert_eq!(got, [true, true, false, true, false, true, false]);
+
+        let mut unlimited = DepthCap::new(0);
+        assert!((0..100).all(|_| unlimited.admit(0, 10)));
+    }
+}
@@ -0,0 +1,170 @@
+// src/stats.rs
+//! 统计检验工具
+
+/// ln(n!)：小 n 直接累加，大 n 用 Stirling 级数
+pub fn ln_factorial(n: usize) -> f64 {
+    if n < 256 {
+        return (2..=n).map(|k| (k as f64).ln()).sum();
+    }
+    let x = n as f64;
+    x * x.ln() - x + 0.5 * (2.0 * std::f64::consts::PI * x).ln() + 1.0 / (12.0 * x)
+        - 1.0 / (360.0 * x * x * x)
+}
+
+/// ln C(n, k)
+pub fn ln_choose(n: usize, k: usize) -> f64 {
+    ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k)
+}
+
+/// 2x2 列联表 Fisher 精确检验（双侧），表为 [[a, b], [c, d]]
+pub fn fisher_exact(a: usize, b: usize, c: usize, d: usize) -> f64 {
+    let row1 = a + b;
+    let col1 = a + c;
+    let n = a + b + c + d;
+    if n == 0 {
+        return 1.0;
+    }
+
+    // 固定边际下 a = x 的超几何对数概率
+    let denom = ln_choose(n, col1);
+    let ln_p = |x: usize| ln_choose(row1, x) + ln_choose(n - row1, col1 - x) - denom;
+
+    let observed = ln_p(a);
+    let lo = col1.saturating_sub(n - row1);
+    let hi = row1.min(col1);
+    let p: f64 = (lo..=hi)
+        .map(ln_p)
+        .filter(|&lp| lp <= observed + 1e-7)
+        .map(f64::exp)
+        .sum();
+    p.min(1.0)
+}
+
+/// ln Γ(x)，Lanczos 近似（x > 0）
+pub fn ln_gamma(x: f64) -> f64 {
+    const G: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // 反射公式
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let a = G[1..]
+        .iter()
+        .enumerate()
+        .fold(G[0], |acc, (i, &g)| acc + g / (x + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// 测序错误零模型：每个读段碱基以概率 `error_rate` 出错；
+/// `dispersion` > 0 时用 beta-binomial 描述位点间错误率的过度离散
+#[derive(Clone, Copy)]
+pub struct ErrorModel {
+    pub error_rate: f64,
+    pub dispersion: f64,
+}
+
+impl ErrorModel {
+    /// 深度 n 下恰好 k 条错误读段的对数概率
+    fn ln_pmf(&self, k: usize, n: usize) -> f64 {
+        let e = self.error_rate.clamp(1e-12, 1.0 - 1e-12);
+        if self.dispersion <= 0.0 {
+            return ln_choose(n, k) + k as f64 * e.ln() + (n - k) as f64 * (1.0 - e).ln();
+        }
+        let rho = self.dispersion.min(1.0 - 1e-12);
+        let a = e * (1.0 - rho) / rho;
+        let b = (1.0 - e) * (1.0 - rho) / rho;
+        let ln_beta = |x: f64, y: f64| ln_gamma(x) + ln_gamma(y) - ln_gamma(x + y);
+        ln_choose(n, k) + ln_beta(k as f64 + a, (n - k) as f64 + b) - ln_beta(a, b)
+    }
+
+    /// 上尾 p 值 P(X >= k | n)：次要等位的 k 条读段全由测序错误造成的概率
+    pub fn p_value(&self, k: usize, n: usize) -> f64 {
+        if k == 0 {
+            return 1.0;
+        }
+        if k > n {
+            return 0.0;
+        }
+        let mean = n as f64 * self.error_rate;
+        let p = if (k as f64) <= mean {
+            // 尾部很重时用补集，只需累加 k 项
+            1.0 - (0..k).map(|x| self.ln_pmf(x, n).exp()).sum::<f64>()
+        } else {
+            // 从 k 往上累加，项足够小时提前结束
+            let mut sum = 0.0;
+            for x in k..=n {
+                let term = self.ln_pmf(x, n).exp();
+                sum += term;
+                if term < sum * 1e-12 {
+                    break;
+                }
+            }
+            sum
+        };
+        p.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tol: f64) -> bool {
+        (a - b).abs() <= tol
+    }
+
+    #[test]
+    fn fisher_two_sided() {
+        // [[3, 1], [1, 3]]：双侧 p = 34/70
+        assert!(close(fisher_exact(3, 1, 1, 3), 0.485_714, 1e-6));
+        assert!(close(fisher_exact(10, 0, 0, 10), 1.082_509e-5, 1e-10));
+        assert_eq!(fisher_exact(5, 5, 5, 5), 1.0);
+        assert_eq!(fisher_exact(0, 0, 0, 0), 1.0);
+    }
+
+    #[test]
+    fn ln_gamma_and_factorial() {
+        assert!(close(ln_gamma(5.0), 24f64.ln(), 1e-12));
+        assert!(close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), 1e-12));
+        assert!(close(ln_gamma(1.0), 0.0, 1e-12));
+        // Stirling 级数在切换点两侧与直接累加一致
+        for n in [255, 256, 300, 1000] {
+            assert!(close(ln_factorial(n), ln_gamma(n as f64 + 1.0), 1e-9 * n as f64));
+        }
+    }
+
+    #[test]
+    fn binomial_upper_tail() {
+        let model = ErrorModel { error_rate: 0.01, dispersion: 0.0 };
+        // P(X >= 3 | n = 100, e = 0.01)
+        assert!(close(model.p_value(3, 100), 0.079_373, 1e-6));
+        assert_eq!(model.p_value(0, 100), 1.0);
+        assert_eq!(model.p_value(101, 100), 0.0);
+        // k 不超过均值时走补集分支
+        assert!(close(model.p_value(1, 100), 1.0 - 0.99f64.powi(100), 1e-12));
+    }
+
+    #[test]
+    fn beta_binomial_tail() {
+        let binomial = ErrorModel { error_rate: 0.01, dispersion: 0.0 };
+        let near = ErrorModel { error_rate: 0.01, dispersion: 1e-9 };
+        assert!(close(near.p_value(3, 100), binomial.p_value(3, 100), 1e-6));
+        // 过度离散时尾部更重，分布仍归一
+        let wide = ErrorModel { error_rate: 0.01, dispersion: 0.05 };
+        assert!(wide.p_value(5, 100) > binomial.p_value(5, 100));
+        let total: f64 = (0..=100).map(|k| wide.ln_pmf(k, 100).exp()).sum();
+        assert!(close(total, 1.0, 1e-9));
+    }
+}
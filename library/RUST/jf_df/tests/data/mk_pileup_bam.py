@@ -0,0 +1,132 @@
+#!/usr/bin/env python3
+# 生成 jf_df BAM 读取测试用的 pileup.bam / pileup.bam.bai（不依赖 samtools）
+#
+# BGZF 块故意切得很小（每块 64 字节未压缩数据），头部和记录都跨块；
+# BAI 按 SAM 规范写 bin 和 16 kb 线性索引。各读段在位点上的预期观测
+# 在 pileup.mpileup 中，改动读段后用 mk_pileup_mpileup.sh 重新生成。
+#
+# 用法：python3 mk_pileup_bam.py pileup.bam
+import re
+import struct
+import sys
+import zlib
+
+BLOCK = 64
+REFS = [('chr1', 40000), ('chr2', 500)]
+OPS = 'MIDNSHP=X'
+
+# 名字、FLAG、tid、0-based 起点、MAPQ、CIGAR、序列、质量、MD
+READS = [
+    ('r1', 0x1 | 0x2 | 0x20, 0, 95, 60, '10M', 'AAAAAAAAAA', [30] * 10, None),
+    ('r1', 0x1 | 0x2 | 0x10, 0, 97, 60, '10M', 'AAAAACAAAA', [30] * 10, None),  # 与配对读段重叠
+    ('r2', 0x10, 0, 98, 60, '2M1D5M', 'CCGGGGG', [40] * 7, '2^A5'),
+    ('r3', 0, 0, 99, 10, '5M', 'TTTTT', [40] * 5, None),  # MAPQ 低
+    ('r4', 0x1, 0, 99, 60, '5M', 'GGGGG', [40] * 5, None),  # 非正常配对
+    ('r5', 0x400, 0, 99, 60, '5M', 'GGGGG', [40] * 5, None),  # 重复
+    ('r6', 0x100, 0, 99, 60, '5M', 'GGGGG', [40] * 5, None),  # 次要比对
+    ('r7', 0, 0, 99, 60, '2S3M2I2M', 'TTGATCCAG', [40, 40, 10, 40, 40, 40, 40, 40, 40], None),
+    ('r12', 0, 0, 15999, 60, '10M3990N12M', 'T' * 10 + 'G' * 12, [30] * 22, None),  # bin 585
+    ('r11', 0, 0, 16379, 60, '12M', 'T' * 12, [30] * 12, None),  # bin 585，不覆盖任何位点
+    ('r8', 0, 0, 19995, 60, '10M', 'ACGTACGTAC', [35] * 10, None),
+    ('r9', 0x10, 0, 19999, 60, '4M2D3M', 'AAAAGGG', [40] * 7, '4^TT3'),
+    ('r10', 0, 0, 32995, 60, '10M', 'C' * 10, [20] * 10, None),
+    ('r13', 0x10, 1, 9, 60, '5M', 'ACGTA', [35] * 5, None),
+    ('r14', 0, 1, 11, 60, '3M', 'GGT', [25] * 3, None),
+]
+
+
+def bgzf_block(data):
+    c = zlib.compressobj(6, zlib.DEFLATED, -15)
+    cdata = c.compress(data) + c.flush()
+    bsize = 12 + 6 + len(cdata) + 8 - 1
+    head = struct.pack('<BBBBIBBHBBHH', 31, 139, 8, 4, 0, 0, 255, 6, 66, 67, 2, bsize)
+    return head + cdata + struct.pack('<II', zlib.crc32(data) & 0xffffffff, len(data))
+
+
+def reg2bin(beg, end):
+    end -= 1
+    for shift, offset in [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)]:
+        if beg >> shift == end >> shift:
+            return offset + (beg >> shift)
+    return 0
+
+
+def parse_cigar(cigar):
+    return [(int(n), OPS.index(o)) for n, o in re.findall(r'(\d+)([MIDNSHP=X])', cigar)]
+
+
+def ref_end(pos, cig):
+    return pos + sum(n for n, o in cig if OPS[o] in 'MDN=X')
+
+
+def record(name, flag, tid, pos, mapq, cigar, seq, qual, md):
+    cig = parse_cigar(cigar)
+    codes = '=ACMGRSVTWYHKDBN'
+    s = [codes.index(c) for c in seq]
+    if len(s) % 2:
+        s.append(0)
+    packed = bytes((s[i] << 4) | s[i + 1] for i in range(0, len(s), 2))
+    nm = name.encode() + b'\x00'
+    bin_ = reg2bin(pos, ref_end(pos, cig))
+    body = struct.pack('<iiBBHHHIiii', tid, pos, len(nm), mapq, bin_, len(cig), flag,
+                       len(seq), -1, -1, 0)
+    body += nm + b''.join(struct.pack('<I', (n << 4) | o) for n, o in cig) + packed + bytes(qual)
+    if md:
+        body += b'MDZ' + md.encode() + b'\x00'
+    return struct.pack('<I', len(body)) + body
+
+
+def main(path):
+    text = b''.join(b'@SQ\tSN:%s\tLN:%d\n' % (n.encode(), l) for n, l in REFS)
+    data = b'BAM\x01' + struct.pack('<I', len(text)) + text + struct.pack('<I', len(REFS))
+    for n, l in REFS:
+        data += struct.pack('<I', len(n) + 1) + n.encode() + b'\x00' + struct.pack('<I', l)
+
+    spans = []
+    for r in READS:
+        rec = record(*r)
+        spans.append((r[2], r[3], ref_end(r[3], parse_cigar(r[5])), len(data), len(data) + len(rec)))
+        data += rec
+
+    blocks = [bgzf_block(data[i:i + BLOCK]) for i in range(0, len(data), BLOCK)]
+    coffsets = [0]
+    for b in blocks:
+        coffsets.append(coffsets[-1] + len(b))
+    with open(path, 'wb') as f:
+        f.write(b''.join(blocks) + bgzf_block(b''))
+
+    def voffset(x):
+        return (coffsets[x // BLOCK] << 16) | (x % BLOCK)
+
+    bai = b'BAI\x01' + struct.pack('<I', len(REFS))
+    for tid in range(len(REFS)):
+        bins = {}
+        linear = []
+        for t, beg, end, ubeg, uend in spans:
+            if t != tid:
+                continue
+            chunks = bins.setdefault(reg2bin(beg, end), [])
+            vbeg, vend = voffset(ubeg), voffset(uend)
+            if chunks and chunks[-1][1] == vbeg:
+                chunks[-1] = (chunks[-1][0], vend)
+            else:
+                chunks.append((vbeg, vend))
+            for w in range(beg >> 14, ((end - 1) >> 14) + 1):
+                linear.extend([None] * (w + 1 - len(linear)))
+                if linear[w] is None or vbeg < linear[w]:
+                    linear[w] = vbeg
+        # 没有读段的窗口取前一个窗口的值
+        for w in range(len(linear)):
+            if linear[w] is None:
+                linear[w] = linear[w - 1] if w else 0
+        bai += struct.pack('<I', len(bins))
+        for bin_, chunks in sorted(bins.items()):
+            bai += struct.pack('<II', bin_, len(chunks))
+            bai += b''.join(struct.pack('<QQ', b, e) for b, e in chunks)
+        bai += struct.pack('<I', len(linear)) + b''.join(struct.pack('<Q', v) for v in linear)
+    with open(path + '.bai', 'wb') as f:
+        f.write(bai)
+
+
+if __name__ == '__main__':
+    main(sys.argv[1])
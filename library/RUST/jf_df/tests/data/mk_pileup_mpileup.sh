@@ -0,0 +1,34 @@
+#!/bin/sh
+# 用 samtools 重新生成 pileup.mpileup（jf_df 测试中 BAM 模式的对照）
+#
+# 参数与 MOST-2.sh 相同（-q 20 -Q 20 -A -a，-d 取缺省 8000）。另给一条参考序列，
+# 只在读段 MD 标签里的缺失处写出碱基、其余为 N，使 mpileup 写出的缺失碱基与
+# BAM 模式按 MD 还原的一致；给了参考序列时 samtools 缺省做 BAQ，MOST-2.sh 不给
+# 参考序列、本来就不做，这里用 -B 关掉。位点列表里 `contig:pos` 的行先换成制表符分隔。
+#
+# 用法：sh mk_pileup_mpileup.sh（需要 samtools 和 python3；改动读段后先运行 mk_pileup_bam.py）
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+python3 - "$tmp/ref.fa" <<'PY'
+import re
+import sys
+from mk_pileup_bam import READS, REFS
+
+ref = {name: ['N'] * length for name, length in REFS}
+for _, _, tid, pos, _, _, _, _, md in READS:
+    p = pos
+    for match, deleted, _ in re.findall(r'(\d+)|\^([A-Z]+)|([A-Z])', md or ''):
+        if deleted:
+            ref[REFS[tid][0]][p:p + len(deleted)] = deleted
+        p += int(match) if match else len(deleted) or 1
+with open(sys.argv[1], 'w') as f:
+    for name, _ in REFS:
+        f.write('>%s\n%s\n' % (name, ''.join(ref[name])))
+PY
+
+tr ':' '\t' < sites.txt > "$tmp/sites.pos"
+samtools faidx "$tmp/ref.fa"
+samtools mpileup -q 20 -Q 20 -A -a -B -f "$tmp/ref.fa" -l "$tmp/sites.pos" pileup.bam > pileup.mpileup
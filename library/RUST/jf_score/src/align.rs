@@ -0,0 +1,87 @@
+// src/align.rs
+//! 一致性序列与数据库按位点标识对齐
+//!
+//! 数据库 `2k-snp.fa` 的各列与位点列表 `2k.add` 一一对应；RefBuild 在一致性序列旁
+//! 写 `.sites` 旁注，逐行记录每一位的位点。两者都在时按 [`SiteId`] 对齐，
+//! 不再依赖 jf_df / samtools 的输出顺序与数据库列顺序恰好一致
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use site_id::SiteId;
+
+/// 第 k 个数据库位点在一致性序列中的下标，一致性序列里没有的为 None
+pub struct SiteMap {
+    index: Vec<Option<usize>>,
+}
+
+impl SiteMap {
+    /// 两边有一边是只有位置的旧列表时只按位置匹配；重复的位点取第一次出现
+    pub fn new(db: &[SiteId], consensus: &[SiteId]) -> Self {
+        let legacy = |sites: &[SiteId]| sites.iter().all(|s| s.contig.is_empty());
+        let by_pos = legacy(db) || legacy(consensus);
+        let key = |s: &SiteId| if by_pos { SiteId::new("", s.pos) } else { s.clone() };
+        let mut at: HashMap<SiteId, usize> = HashMap::with_capacity(consensus.len());
+        for (i, site) in consensus.iter().enumerate() {
+            at.entry(key(site)).or_insert(i);
+        }
+        SiteMap { index: db.iter().map(|s| at.get(&key(s)).copied()).collect() }
+    }
+
+    /// 对齐后的长度，即数据库位点数
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// 一致性序列中找不到的数据库位点数
+    pub fn missing(&self) -> usize {
+        self.index.iter().filter(|i| i.is_none()).count()
+    }
+
+    /// 按数据库位点顺序重排一致性序列（或逐位的权重），缺少的位点填 `missing`
+    pub fn apply<T: Copy>(&self, values: &[T], missing: T) -> Vec<T> {
+        self.index.iter().map(|i| i.and_then(|i| values.get(i).copied()).unwrap_or(missing)).collect()
+    }
+}
+
+/// RefBuild 为一致性序列写的 `.sites` 旁注：去掉 `.gz` 后换扩展名
+pub fn sidecar(input: &str) -> PathBuf {
+    let base = input.strip_suffix(".gz").unwrap_or(input);
+    Path::new(base).with_extension("sites")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sites(ids: &[&str]) -> Vec<SiteId> {
+        ids.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn reorders_by_site_id() {
+        // 数据库按 2k.add 的顺序，一致性序列按 BAM 头部顺序，且少一个位点
+        let db = sites(&["chr2:5", "chr1:10", "chr1:20", "chr3:1"]);
+        let consensus = sites(&["chr1:10", "chr1:20", "chr2:5"]);
+        let map = SiteMap::new(&db, &consensus);
+        assert_eq!((map.len(), map.missing()), (4, 1));
+        assert_eq!(map.apply(b"ACG", b'-'), b"GAC-");
+        assert_eq!(map.apply(&[0.5, 1.0, 0.25], 0.0), [0.25, 0.5, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn legacy_lists_match_by_position() {
+        let db = sites(&["30", "10"]);
+        let consensus = sites(&["chr1:10", "chr1:30"]);
+        assert_eq!(SiteMap::new(&db, &consensus).apply(b"AC", b'-'), b"CA");
+        // 都带序列名时不同序列的同一位置不混淆
+        let db = sites(&["chr2:10"]);
+        assert_eq!(SiteMap::new(&db, &consensus).missing(), 1);
+    }
+
+    #[test]
+    fn sidecar_path() {
+        assert_eq!(sidecar("./output/really-ref.fa"), PathBuf::from("./output/really-ref.sites"));
+        assert_eq!(sidecar("s.fa.gz"), PathBuf::from("s.sites"));
+    }
+}
@@ -0,0 +1,342 @@
+// src/kernel.rs
+//! SNP 距离计算内核：x86 AVX-512 / AVX2、aarch64 NEON、可移植的按字（SWAR）版本和标量版本
+//!
+//! 所有内核返回相同的 (SNP 距离, 可比较位点数)：两条序列都不是 `-` 的位点才可比较，
+//! 其中字节不同的计为差异。带位点权重时用 [`weighted_scalar`]，按权重累加两者
+
+/// 距离计算内核
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    Avx512,
+    Avx2,
+    Neon,
+    /// 每次比较 8 字节的可移植版本，任何平台可用
+    Swar,
+    Scalar,
+}
+
+impl Kernel {
+    /// 按优先级排列，自动选择时取第一个可用的
+    pub const ALL: [Kernel; 5] = [Kernel::Avx512, Kernel::Avx2, Kernel::Neon, Kernel::Swar, Kernel::Scalar];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kernel::Avx512 => "avx512",
+            Kernel::Avx2 => "avx2",
+            Kernel::Neon => "neon",
+            Kernel::Swar => "swar",
+            Kernel::Scalar => "scalar",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kernel> {
+        Kernel::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    /// 当前 CPU 是否支持
+    pub fn is_available(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => is_x86_feature_detected!("avx512bw"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            Kernel::Swar | Kernel::Scalar => true,
+            _ => false,
+        }
+    }
+
+    /// 当前 CPU 上最快的可用内核
+    pub fn detect() -> Kernel {
+        Kernel::ALL.into_iter().find(|k| k.is_available()).unwrap_or(Kernel::Scalar)
+    }
+
+    /// 返回 (SNP 距离, 可比较位点数)
+    ///
+    /// SIMD 版本按参考长度读取查询序列，两者必须等长；内核必须在当前 CPU 上可用
+    pub fn distance(self, ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+        assert_eq!(ref_seq.len(), query.len(), "查询序列与参考序列长度不一致");
+        assert!(self.is_available(), "当前 CPU 不支持 {} 内核", self.name());
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => unsafe { x86::distance_avx512(ref_seq, query) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { x86::distance_avx2(ref_seq, query) },
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => unsafe { neon::distance_neon(ref_seq, query) },
+            Kernel::Swar => distance_swar(ref_seq, query),
+            _ => distance_scalar(ref_seq, query),
+        }
+    }
+}
+
+// 标量版本
+pub fn distance_scalar(ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+    let dash = b'-';
+    let mut distance = 0;
+    let mut sites = 0;
+
+    for (r, q) in ref_seq.iter().zip(query.iter()) {
+        if *r != dash && *q != dash {
+            sites += 1;
+            if r != q {
+                distance += 1;
+            }
+        }
+    }
+
+    (distance, sites)
+}
+
+/// 加权版本：返回 (差异位点权重和, 可比较位点权重和)，`weights` 与参考序列等长
+pub fn weighted_scalar(ref_seq: &[u8], query: &[u8], weights: &[f64]) -> (f64, f64) {
+    let dash = b'-';
+    let mut distance = 0.0;
+    let mut sites = 0.0;
+
+    for ((r, q), w) in ref_seq.iter().zip(query.iter()).zip(weights) {
+        if *r != dash && *q != dash {
+            sites += w;
+            if r != q {
+                distance += w;
+            }
+        }
+    }
+
+    (distance, sites)
+}
+
+const LO7: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// 每个非零字节的最高位置 1，其余位为 0（低 7 位相加不会跨字节进位）
+#[inline]
+fn nonzero_bytes(x: u64) -> u64 {
+    (((x & LO7) + LO7) | x) & HI
+}
+
+// 可移植版本：一次处理 8 字节
+fn distance_swar(ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+    let dash = u64::from_ne_bytes([b'-'; 8]);
+    let mut distance = 0;
+    let mut sites = 0;
+
+    let mut r_chunks = ref_seq.chunks_exact(8);
+    let mut q_chunks = query.chunks_exact(8);
+    for (r, q) in r_chunks.by_ref().zip(q_chunks.by_ref()) {
+        let a = u64::from_ne_bytes(r.try_into().unwrap());
+        let b = u64::from_ne_bytes(q.try_into().unwrap());
+        let both = nonzero_bytes(a ^ dash) & nonzero_bytes(b ^ dash);
+        let ne = nonzero_bytes(a ^ b) & both;
+        distance += ne.count_ones() as usize;
+        sites += both.count_ones() as usize;
+    }
+
+    // 处理剩余字节
+    let (d, n) = distance_scalar(r_chunks.remainder(), q_chunks.remainder());
+    (distance + d, sites + n)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::distance_scalar;
+
+    // AVX-512 比较函数：返回 (差异位数, 可比较位数)
+    #[target_feature(enable = "avx512bw")]
+    unsafe fn simd_compare_avx512(a: __m512i, b: __m512i, dash: __m512i) -> (u32, u32) {
+        // 比较不相等的位
+        let ne = _mm512_cmpneq_epi8_mask(a, b);
+
+        // 比较不是破折号的位
+        let not_dash_a = _mm512_cmpneq_epi8_mask(a, dash);
+        let not_dash_b = _mm512_cmpneq_epi8_mask(b, dash);
+
+        // 合并所有条件
+        let both = not_dash_a & not_dash_b;
+        let mask = ne & both;
+
+        (mask.count_ones(), both.count_ones())
+    }
+
+    // AVX2 比较函数：返回 (差异位数, 可比较位数)
+    #[target_feature(enable = "avx2")]
+    unsafe fn simd_compare_avx2(a: __m256i, b: __m256i, dash: __m256i) -> (u32, u32) {
+        let ne = _mm256_cmpeq_epi8(a, b);
+        let ne = _mm256_andnot_si256(ne, _mm256_set1_epi8(-1));
+
+        let not_dash_a = _mm256_cmpeq_epi8(a, dash);
+        let not_dash_a = _mm256_andnot_si256(not_dash_a, _mm256_set1_epi8(-1));
+
+        let not_dash_b = _mm256_cmpeq_epi8(b, dash);
+        let not_dash_b = _mm256_andnot_si256(not_dash_b, _mm256_set1_epi8(-1));
+
+        let both = _mm256_and_si256(not_dash_a, not_dash_b);
+        let mask = _mm256_and_si256(ne, both);
+        let mask = _mm256_movemask_epi8(mask);
+        let both = _mm256_movemask_epi8(both);
+        (mask.count_ones(), both.count_ones())
+    }
+
+    // AVX-512 版本
+    #[target_feature(enable = "avx512bw")]
+    pub unsafe fn distance_avx512(ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+        let dash_simd = _mm512_set1_epi8(b'-' as i8);
+        let mut distance = 0;
+        let mut sites = 0;
+        let mut i = 0;
+
+        // SIMD 处理（64字节块）
+        let simd_chunks = ref_seq.len() / 64;
+        for _ in 0..simd_chunks {
+            let ref_simd = _mm512_loadu_si512(ref_seq[i..].as_ptr() as *const __m512i);
+            let query_simd = _mm512_loadu_si512(query[i..].as_ptr() as *const __m512i);
+            let (d, n) = simd_compare_avx512(ref_simd, query_simd, dash_simd);
+            distance += d as usize;
+            sites += n as usize;
+            i += 64;
+        }
+
+        // 处理剩余字节
+        let (d, n) = distance_scalar(&ref_seq[i..], &query[i..]);
+        (distance + d, sites + n)
+    }
+
+    // AVX2 版本
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn distance_avx2(ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+        let dash_simd = _mm256_set1_epi8(b'-' as i8);
+        let mut distance = 0;
+        let mut sites = 0;
+        let mut i = 0;
+
+        // SIMD 处理（32字节块）
+        let simd_chunks = ref_seq.len() / 32;
+        for _ in 0..simd_chunks {
+            let ref_simd = _mm256_loadu_si256(ref_seq[i..].as_ptr() as *const __m256i);
+            let query_simd = _mm256_loadu_si256(query[i..].as_ptr() as *const __m256i);
+            let (d, n) = simd_compare_avx2(ref_simd, query_simd, dash_simd);
+            distance += d as usize;
+            sites += n as usize;
+            i += 32;
+        }
+
+        // 处理剩余字节
+        let (d, n) = distance_scalar(&ref_seq[i..], &query[i..]);
+        (distance + d, sites + n)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::distance_scalar;
+
+    // NEON 版本（16字节块）：比较结果为全 1 / 全 0 字节，右移 7 位后横向求和即为计数
+    #[target_feature(enable = "neon")]
+    pub unsafe fn distance_neon(ref_seq: &[u8], query: &[u8]) -> (usize, usize) {
+        let dash = vdupq_n_u8(b'-');
+        let mut distance = 0;
+        let mut sites = 0;
+        let mut i = 0;
+
+        let simd_chunks = ref_seq.len() / 16;
+        for _ in 0..simd_chunks {
+            let a = vld1q_u8(ref_seq[i..].as_ptr());
+            let b = vld1q_u8(query[i..].as_ptr());
+            let not_dash_a = vmvnq_u8(vceqq_u8(a, dash));
+            let not_dash_b = vmvnq_u8(vceqq_u8(b, dash));
+            let both = vandq_u8(not_dash_a, not_dash_b);
+            let ne = vandq_u8(vmvnq_u8(vceqq_u8(a, b)), both);
+            distance += vaddvq_u8(vshrq_n_u8::<7>(ne)) as usize;
+            sites += vaddvq_u8(vshrq_n_u8::<7>(both)) as usize;
+            i += 16;
+        }
+
+        // 处理剩余字节
+        let (d, n) = distance_scalar(&ref_seq[i..], &query[i..]);
+        (distance + d, sites + n)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 固定种子的 xorshift，生成含 `-`、N、兼并码和小写碱基的序列（各模块测试共用）
+    pub(crate) fn random_seq(state: &mut u64, len: usize) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ACGTACGT-NRYacgtn";
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                ALPHABET[(*state % ALPHABET.len() as u64) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scalar_counts_comparable_sites() {
+        assert_eq!(distance_scalar(b"ACGT-A", b"ACCA-"), (2, 4));
+        assert_eq!(distance_scalar(b"AC-T", b"-CGA"), (1, 2));
+        assert_eq!(distance_scalar(b"", b""), (0, 0));
+    }
+
+    #[test]
+    fn weighted_scalar_sums_weights() {
+        let w = [1.0, 0.5, 2.0, 0.25, 4.0, 8.0];
+        assert_eq!(weighted_scalar(b"ACGT-A", b"ACCA-", &w), (2.25, 3.75));
+        let mut state = 7;
+        let r = random_seq(&mut state, 500);
+        let q = random_seq(&mut state, 500);
+        let (d, n) = distance_scalar(&r, &q);
+        assert_eq!(weighted_scalar(&r, &q, &[1.0; 500]), (d as f64, n as f64));
+    }
+
+    #[test]
+    fn every_kernel_matches_scalar() {
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+        let lengths = [0, 1, 7, 8, 9, 15, 16, 17, 31, 32, 33, 63, 64, 65, 127, 128, 129, 1000, 4099];
+        for len in lengths {
+            for _ in 0..8 {
+                let r = random_seq(&mut state, len);
+                let q = random_seq(&mut state, len);
+                let expected = distance_scalar(&r, &q);
+                for kernel in Kernel::ALL.into_iter().filter(|k| k.is_available()) {
+                    assert_eq!(kernel.distance(&r, &q), expected, "{} 内核，长度 {}", kernel.name(), len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identical_and_all_dash() {
+        let mut state = 42;
+        let r = random_seq(&mut state, 300);
+        let dashes = vec![b'-'; 300];
+        for kernel in Kernel::ALL.into_iter().filter(|k| k.is_available()) {
+            let (d, n) = kernel.distance(&r, &r);
+            assert_eq!(d, 0, "{}", kernel.name());
+            assert_eq!(n, r.iter().filter(|&&b| b != b'-').count(), "{}", kernel.name());
+            assert_eq!(kernel.distance(&r, &dashes), (0, 0), "{}", kernel.name());
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for kernel in Kernel::ALL {
+            assert_eq!(Kernel::from_name(kernel.name()), Some(kernel));
+        }
+        assert!(Kernel::Scalar.is_available() && Kernel::Swar.is_available());
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_length_mismatch() {
+        Kernel::detect().distance(b"ACGT", b"ACG");
+    }
+}
@@ -1,138 +1,137 @@
-#![cfg(target_arch = "x86_64")]
-
-use std::arch::x86_64::*;
+use std::collections::HashSet;
 use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
-use std::sync::Arc;
+use std::str::FromStr;
 
 use memmap2::Mmap;
 use rayon::prelude::*;
 
-// AVX-512 比较函数
-#[target_feature(enable = "avx512bw")]
-unsafe fn simd_compare_avx512(a: __m512i, b: __m512i, dash: __m512i) -> u32 {
-    // 比较不相等的位
-    let ne = _mm512_cmpneq_epi8_mask(a, b);
-    
-    // 比较不是破折号的位
-    let not_dash_a = _mm512_cmpneq_epi8_mask(a, dash);
-    let not_dash_b = _mm512_cmpneq_epi8_mask(b, dash);
-    
-    // 合并所有条件
-    let mask = ne & not_dash_a & not_dash_b;
-    
-    mask.count_ones()
-}
-
-// AVX2 比较函数
-#[target_feature(enable = "avx2")]
-unsafe fn simd_compare_avx2(a: __m256i, b: __m256i, dash: __m256i) -> u32 {
-    let ne = _mm256_cmpeq_epi8(a, b);
-    let ne = _mm256_andnot_si256(ne, _mm256_set1_epi8(-1));
-    
-    let not_dash_a = _mm256_cmpeq_epi8(a, dash);
-    let not_dash_a = _mm256_andnot_si256(not_dash_a, _mm256_set1_epi8(-1));
-    
-    let not_dash_b = _mm256_cmpeq_epi8(b, dash);
-    let not_dash_b = _mm256_andnot_si256(not_dash_b, _mm256_set1_epi8(-1));
-    
-    let mask = _mm256_and_si256(ne, _mm256_and_si256(not_dash_a, not_dash_b));
-    let mask = _mm256_movemask_epi8(mask);
-    mask.count_ones()
-}
-
-// 主计算函数
-fn calculate_snp_distance(ref_seq: &[u8], query: &[u8]) -> usize {
-    #[cfg(target_arch = "x86_64")]
-    {
-        if is_x86_feature_detected!("avx512bw") {
-            unsafe { calculate_snp_distance_avx512(ref_seq, query) }
-        } else if is_x86_feature_detected!("avx2") {
-            unsafe { calculate_snp_distance_avx2(ref_seq, query) }
-        } else {
-            calculate_snp_distance_scalar(ref_seq, query)
-        }
-    }
-    #[cfg(not(target_arch = "x86_64"))]
-    {
-        calculate_snp_distance_scalar(ref_seq, query)
-    }
-}
+mod align;
+mod kernel;
+mod matrix;
+mod packed;
+mod rank;
+use align::SiteMap;
+use kernel::Kernel;
+use packed::{PackedDb, PackedSeq};
+use rank::{Hit, Threshold};
 
-// AVX-512 版本
-#[target_feature(enable = "avx512bw")]
-unsafe fn calculate_snp_distance_avx512(ref_seq: &[u8], query: &[u8]) -> usize {
-    let dash = b'-';
-    let dash_simd = _mm512_set1_epi8(dash as i8);
-    let mut distance = 0;
-    let mut i = 0;
+/// 数据库 SNP 序列
+const DB_PATH: &str = "./DB/2k-snp.fa";
+/// 数据库各列对应的位点（即 jf_df 的 `--sites` 列表），见 [`align`]
+const DB_SITES: &str = "./DB/2k.add";
 
-    // SIMD 处理（64字节块）
-    let simd_chunks = ref_seq.len() / 64;
-    for _ in 0..simd_chunks {
-        let ref_simd = _mm512_loadu_si512(ref_seq[i..].as_ptr() as *const __m512i);
-        let query_simd = _mm512_loadu_si512(query[i..].as_ptr() as *const __m512i);
-        distance += simd_compare_avx512(ref_simd, query_simd, dash_simd) as usize;
-        i += 64;
+/// IUPAC 码对应的碱基集合（A=1、C=2、G=4、T=8），`-` 等非碱基字符为 0
+///
+/// `N` 不提供任何信息，与 `-` 一样按缺失处理（为 0），否则会算作与所有菌株
+/// 都一致的可比较位点，抬高可比较位点数、压低归一化距离
+fn iupac_mask(b: u8) -> u8 {
+    match b.to_ascii_uppercase() {
+        b'A' => 1,
+        b'C' => 2,
+        b'G' => 4,
+        b'T' => 8,
+        b'M' => 1 | 2,
+        b'R' => 1 | 4,
+        b'W' => 1 | 8,
+        b'S' => 2 | 4,
+        b'Y' => 2 | 8,
+        b'K' => 4 | 8,
+        b'V' => 1 | 2 | 4,
+        b'H' => 1 | 2 | 8,
+        b'D' => 1 | 4 | 8,
+        b'B' => 2 | 4 | 8,
+        _ => 0,
     }
+}
 
-    // 处理剩余字节
-    while i < ref_seq.len() {
-        let r = ref_seq[i];
-        let q = query[i];
-        if r != dash && q != dash && r != q {
-            distance += 1;
-        }
-        i += 1;
+/// 数据库序列的统一写法：ACGT 转大写，N、兼并码等其它字符都按缺失写成 `-`，
+/// 与压缩表示（[`packed`]）的规则一致，字节内核和压缩内核给出相同的距离
+fn canonical(b: u8) -> u8 {
+    match b.to_ascii_uppercase() {
+        b @ (b'A' | b'C' | b'G' | b'T') => b,
+        _ => b'-',
     }
+}
 
-    distance
+/// 参考序列里的兼并碱基位点
+///
+/// 这些位点在参考序列中替换为 `-`，SIMD 比较时跳过，再逐个按碱基集合判断：
+/// 查询碱基落在兼并码包含的碱基内就不算差异。其余位点按 [`canonical`] 统一
+struct Ambiguous {
+    pos: Vec<usize>,
+    masks: Vec<u8>,
 }
 
-// AVX2 版本
-#[target_feature(enable = "avx2")]
-unsafe fn calculate_snp_distance_avx2(ref_seq: &[u8], query: &[u8]) -> usize {
-    let dash = b'-';
-    let dash_simd = _mm256_set1_epi8(dash as i8);
-    let mut distance = 0;
-    let mut i = 0;
+impl Ambiguous {
+    fn split(seq: &mut [u8]) -> Self {
+        let mut pos = Vec::new();
+        let mut masks = Vec::new();
+        for (i, b) in seq.iter_mut().enumerate() {
+            let mask = iupac_mask(*b);
+            if mask != 0 && !mask.is_power_of_two() {
+                pos.push(i);
+                masks.push(mask);
+                *b = b'-';
+            } else {
+                *b = canonical(*b);
+            }
+        }
+        Ambiguous { pos, masks }
+    }
 
-    // SIMD 处理（32字节块）
-    let simd_chunks = ref_seq.len() / 32;
-    for _ in 0..simd_chunks {
-        let ref_simd = _mm256_loadu_si256(ref_seq[i..].as_ptr() as *const __m256i);
-        let query_simd = _mm256_loadu_si256(query[i..].as_ptr() as *const __m256i);
-        distance += simd_compare_avx2(ref_simd, query_simd, dash_simd) as usize;
-        i += 32;
+    /// 返回 (差异位数, 可比较位数)；`query_mask(i)` 给出查询序列第 i 位的碱基集合
+    fn distance(&self, query_mask: impl Fn(usize) -> u8) -> (usize, usize) {
+        let mut distance = 0;
+        let mut sites = 0;
+        for (&i, &mask) in self.pos.iter().zip(&self.masks) {
+            let q = query_mask(i);
+            if q != 0 {
+                sites += 1;
+                distance += (q & mask == 0) as usize;
+            }
+        }
+        (distance, sites)
     }
 
-    // 处理剩余字节
-    while i < ref_seq.len() {
-        let r = ref_seq[i];
-        let q = query[i];
-        if r != dash && q != dash && r != q {
-            distance += 1;
+    /// 加权版本：返回 (差异位点权重和, 可比较位点权重和)
+    fn weighted(&self, query_mask: impl Fn(usize) -> u8, weights: &[f64]) -> (f64, f64) {
+        let mut distance = 0.0;
+        let mut sites = 0.0;
+        for (&i, &mask) in self.pos.iter().zip(&self.masks) {
+            let q = query_mask(i);
+            if q != 0 {
+                sites += weights[i];
+                if q & mask == 0 {
+                    distance += weights[i];
+                }
+            }
         }
-        i += 1;
+        (distance, sites)
     }
+}
 
-    distance
+/// 一条参考序列的比较准备：统一后的字节序列、兼并位点、压缩表示、位点权重，
+/// 以及参与归一化排名所需的最少可比较位点数
+struct Query<'w> {
+    seq: Vec<u8>,
+    ambiguous: Ambiguous,
+    packed: PackedSeq,
+    weights: Option<&'w [f64]>,
+    min_sites: usize,
 }
 
-// 标量版本
-fn calculate_snp_distance_scalar(ref_seq: &[u8], query: &[u8]) -> usize {
-    let dash = b'-';
-    let mut distance = 0;
-    
-    for (r, q) in ref_seq.iter().zip(query.iter()) {
-        if *r != dash && *q != dash && r != q {
-            distance += 1;
-        }
+impl<'w> Query<'w> {
+    fn new(seq: &[u8], weights: Option<&'w [f64]>, min_sites: usize) -> Self {
+        let mut seq = seq.to_vec();
+        let ambiguous = Ambiguous::split(&mut seq);
+        let packed = PackedSeq::pack(&seq);
+        Query { seq, ambiguous, packed, weights, min_sites }
     }
-    
-    distance
 }
 
 // FASTA 记录结构
@@ -141,18 +140,19 @@ struct FastaRecord {
     seq: Vec<u8>,
 }
 
-// 高效 FASTA 解析器
-fn parse_fasta_mmap<P: AsRef<Path>>(path: P) -> Vec<FastaRecord> {
-    let file = File::open(path).expect("Failed to open file");
-    let mmap = unsafe { Mmap::map(&file).expect("Failed to mmap file") };
+// 高效 FASTA 解析器；打不开时错误信息带上路径
+fn parse_fasta_mmap<P: AsRef<Path>>(path: P) -> io::Result<Vec<FastaRecord>> {
+    let path = path.as_ref();
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("'{}': {}", path.display(), e));
+    let file = File::open(path).map_err(with_path)?;
+    let mmap = unsafe { Mmap::map(&file).map_err(with_path)? };
     let data = &mmap[..];
 
     let mut records = Vec::new();
-    let mut lines = data.split(|&b| b == b'\n');
     let mut current_id = None;
     let mut current_seq = Vec::new();
 
-    while let Some(line) = lines.next() {
+    for line in data.split(|&b| b == b'\n') {
         if line.is_empty() {
             continue;
         }
@@ -174,44 +174,608 @@ fn parse_fasta_mmap<P: AsRef<Path>>(path: P) -> Vec<FastaRecord> {
         records.push(FastaRecord { id, seq: current_seq });
     }
 
+    Ok(records)
+}
+
+/// 输入序列校验失败
+#[derive(Debug)]
+enum ScoreError {
+    /// 一致性序列文件里没有记录
+    NoConsensus,
+    /// 与第一条一致性序列长度不一致的样本：(ID, 长度)
+    ConsensusMismatch { expected: usize, records: Vec<(String, usize)> },
+    /// 数据库记录与一致性序列长度不一致：(ID, 长度)
+    LengthMismatch { expected: usize, records: Vec<(String, usize)> },
+    /// 距离矩阵输入中与第一条长度不一致的记录：(ID, 长度)
+    MatrixMismatch { expected: usize, records: Vec<(String, usize)> },
+    /// `--kernel` 给出的内核不存在或当前 CPU 不支持
+    Kernel(String),
+    /// 位点权重文件格式错误：(文件, 说明)
+    Weights(String, String),
+    /// 位点列表与序列对不上：(文件, 说明)
+    Sites(String, String),
+    /// 命令行参数的取值无法解析：(参数, 取值)
+    Usage(&'static str, String),
+    /// 读取 FASTA、生成或读取压缩数据库、写出结果失败
+    Io(io::Error),
+}
+
+impl From<io::Error> for ScoreError {
+    fn from(e: io::Error) -> Self {
+        ScoreError::Io(e)
+    }
+}
+
+impl fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, against, expected, records) = match self {
+            ScoreError::NoConsensus => return write!(f, "一致性序列文件中没有记录"),
+            ScoreError::Kernel(name) => {
+                let names: Vec<&str> = std::iter::once("packed")
+                    .chain(Kernel::ALL.into_iter().filter(|k| k.is_available()).map(Kernel::name))
+                    .collect();
+                return write!(f, "内核 '{}' 不可用（当前可用: {}）", name, names.join(" / "));
+            }
+            ScoreError::Io(e) => return write!(f, "读写失败: {}", e),
+            ScoreError::Weights(path, msg) => return write!(f, "位点权重文件 '{}': {}", path, msg),
+            ScoreError::Sites(path, msg) => return write!(f, "位点列表 '{}': {}", path, msg),
+            ScoreError::Usage(flag, value) => return write!(f, "{} 的取值无法解析: '{}'", flag, value),
+            ScoreError::ConsensusMismatch { expected, records } => {
+                ("一致性序列", "第一条一致性序列", expected, records)
+            }
+            ScoreError::LengthMismatch { expected, records } => ("数据库记录", "一致性序列", expected, records),
+            ScoreError::MatrixMismatch { expected, records } => ("记录", "第一条记录", expected, records),
+        };
+        write!(f, "{} 条{}长度与{}（{}）不一致:", records.len(), what, against, expected)?;
+        for (id, len) in records {
+            write!(f, "\n  {}\t{}", id, len)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ScoreError {}
+
+/// 长度与 `expected` 不一致的记录
+fn mismatched<'a>(
+    records: impl Iterator<Item = (&'a str, usize)>,
+    expected: usize,
+) -> Vec<(String, usize)> {
     records
+        .filter(|&(_, len)| len != expected)
+        .map(|(id, len)| (id.to_string(), len))
+        .collect()
+}
+
+/// `--kernel` 的取值：packed 为压缩表示（返回 None），auto 为当前 CPU 上最快的字节内核
+fn parse_kernel(name: String) -> Result<Option<Kernel>, ScoreError> {
+    Ok(match name.as_str() {
+        "packed" => None,
+        "auto" => Some(Kernel::detect()),
+        _ => Some(
+            Kernel::from_name(&name)
+                .filter(|k| k.is_available())
+                .ok_or(ScoreError::Kernel(name))?,
+        ),
+    })
+}
+
+/// 解析 `flag` 后面的取值，缺失或无法解析时报错
+fn parse_value<T: FromStr>(flag: &'static str, value: Option<String>) -> Result<T, ScoreError> {
+    let value = value.unwrap_or_default();
+    value.parse().map_err(|_| ScoreError::Usage(flag, value))
+}
+
+/// 读取位点权重：每行一个位点，按一致性序列顺序，取最后一列
+///
+/// RefBuild 的 `.support` 旁注（最后一列为一致性碱基频率）可以直接使用；
+/// 空行和 `#` 开头的行跳过；只有第一个数据行的最后一列可以不是数字（视为表头），
+/// 之后的非数字行报错，不会把截断或写错的文件当作表头跳过
+fn read_weights(path: &str, expected: usize) -> Result<Vec<f64>, ScoreError> {
+    let err = |msg: String| ScoreError::Weights(path.to_string(), msg);
+    let text = std::fs::read_to_string(path).map_err(|e| err(e.to_string()))?;
+    let mut weights = Vec::with_capacity(expected);
+    let mut first = true;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let header = std::mem::replace(&mut first, false);
+        let last = line.split_whitespace().next_back().unwrap_or("");
+        match last.parse::<f64>() {
+            Ok(w) if w.is_finite() && w >= 0.0 => weights.push(w),
+            Ok(_) => return Err(err(format!("第 {} 行权重须为非负数: {}", n + 1, last))),
+            Err(_) if header => continue,
+            Err(_) => return Err(err(format!("第 {} 行无法解析权重: {}", n + 1, last))),
+        }
+    }
+    if weights.len() != expected {
+        return Err(err(format!("{} 个权重，一致性序列 {} 个位点", weights.len(), expected)));
+    }
+    Ok(weights)
+}
+
+/// 读取两边的位点列表并对齐；一致性位点数须与序列长度一致，且至少有一个共同位点
+fn site_map(sites_path: &str, db_sites_path: &str, expected: usize) -> Result<SiteMap, ScoreError> {
+    let err = |path: &str, msg: String| ScoreError::Sites(path.to_string(), msg);
+    let consensus = site_id::read_list(sites_path)?;
+    if consensus.len() != expected {
+        let msg = format!("{} 个位点，一致性序列 {} 位", consensus.len(), expected);
+        return Err(err(sites_path, msg));
+    }
+    let db = site_id::read_list(db_sites_path)?;
+    let map = SiteMap::new(&db, &consensus);
+    if map.missing() == map.len() {
+        return Err(err(db_sites_path, format!("与 '{}' 没有共同位点", sites_path)));
+    }
+    if map.missing() > 0 {
+        eprintln!(
+            "警告: {} 个数据库位点（'{}'）不在一致性序列的位点列表中，按缺失处理",
+            map.missing(),
+            db_sites_path
+        );
+    }
+    Ok(map)
+}
+
+/// 数据库序列：缺省为内存映射的压缩表示，`--kernel` 指定字节内核时直接读 FASTA
+enum Db {
+    Packed(PackedDb),
+    Fasta(Vec<FastaRecord>, Kernel),
+}
+
+impl Db {
+    /// `kernel` 为 None 时用压缩数据库，否则读 FASTA 并按 [`canonical`] 统一
+    fn open(path: &Path, kernel: Option<Kernel>) -> Result<Db, ScoreError> {
+        Ok(match kernel {
+            None => Db::Packed(PackedDb::open_or_build(path)?),
+            Some(kernel) => {
+                let mut records = parse_fasta_mmap(path)?;
+                for r in &mut records {
+                    r.seq.iter_mut().for_each(|b| *b = canonical(*b));
+                }
+                Db::Fasta(records, kernel)
+            }
+        })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Db::Packed(db) => db.len(),
+            Db::Fasta(records, _) => records.len(),
+        }
+    }
+
+    fn id(&self, i: usize) -> &str {
+        match self {
+            Db::Packed(db) => db.id(i),
+            Db::Fasta(records, _) => &records[i].id,
+        }
+    }
+
+    fn seq_len(&self, i: usize) -> usize {
+        match self {
+            Db::Packed(db) => db.seq_len(i),
+            Db::Fasta(records, _) => records[i].seq.len(),
+        }
+    }
+
+    /// 第 `i` 条记录相对参考序列的距离；给了位点权重时另算加权距离，按加权距离排名
+    fn hit(&self, i: usize, query: &Query) -> Hit<'_> {
+        let ambiguous = &query.ambiguous;
+        match self {
+            Db::Packed(db) => {
+                let (r, q) = (query.packed.view(), db.record(i));
+                let (d1, n1) = packed::distance(&r, &q);
+                let (d2, n2) = ambiguous.distance(|p| q.mask(p));
+                let weighted = query.weights.map(|w| {
+                    let (d1, n1) = packed::weighted_distance(&r, &q, w);
+                    let (d2, n2) = ambiguous.weighted(|p| q.mask(p), w);
+                    (d1 + d2, n1 + n2)
+                });
+                let sites = n1 + n2;
+                let sparse = sites < query.min_sites;
+                Hit { id: db.id(i), distance: d1 + d2, sites, weighted, sparse }
+            }
+            Db::Fasta(records, kernel) => {
+                let (r, q) = (&query.seq, &records[i].seq);
+                let (d1, n1) = kernel.distance(r, q);
+                let (d2, n2) = ambiguous.distance(|p| iupac_mask(q[p]));
+                let weighted = query.weights.map(|w| {
+                    let (d1, n1) = kernel::weighted_scalar(r, q, w);
+                    let (d2, n2) = ambiguous.weighted(|p| iupac_mask(q[p]), w);
+                    (d1 + d2, n1 + n2)
+                });
+                let sites = n1 + n2;
+                let sparse = sites < query.min_sites;
+                Hit { id: &records[i].id, distance: d1 + d2, sites, weighted, sparse }
+            }
+        }
+    }
+
+    /// 对 `keep` 中的记录并行计算距离
+    fn hits<'a: 'q, 'q>(
+        &'a self,
+        query: &'q Query,
+        keep: &'q [usize],
+    ) -> impl ParallelIterator<Item = Hit<'a>> + 'q {
+        keep.par_iter().map(move |&i| self.hit(i, query))
+    }
 }
 
 fn main() {
-    // 获取命令行参数
-    let args: Vec<String> = env::args().collect();
-    let output_count = if args.len() > 1 {
-        args[1].parse().unwrap_or(20)
-    } else {
-        20
+    if let Err(e) = run() {
+        eprintln!("错误: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), ScoreError> {
+    // `jf_score matrix ...`：两两距离矩阵，见 matrix.rs
+    if env::args().nth(1).as_deref() == Some("matrix") {
+        return matrix::run(env::args().skip(2));
+    }
+    let opts = parse_args(env::args().skip(1))?;
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    score(&opts, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// 评分模式的参数
+struct Opts {
+    output_count: usize,
+    input: String,
+    db: String,
+    skip_bad: bool,
+    tsv: bool,
+    kernel: Option<Kernel>,
+    threshold: Option<Threshold>,
+    cap: usize,
+    weights_path: Option<String>,
+    min_sites: usize,
+    sites_path: Option<String>,
+    db_sites_path: Option<String>,
+}
+
+impl Default for Opts {
+    fn default() -> Self {
+        Opts {
+            output_count: 20,
+            input: "./output/really-ref.fa".to_string(),
+            db: DB_PATH.to_string(),
+            skip_bad: false,
+            tsv: false,
+            kernel: None,
+            threshold: None,
+            cap: 100,
+            weights_path: None,
+            min_sites: 10,
+            sites_path: None,
+            db_sites_path: None,
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Opts, ScoreError> {
+    // 获取命令行参数：输出条数、--input（一致性序列，可以是多个样本的 multi-FASTA）、
+    // --db（数据库 SNP 序列，缺省 ./DB/2k-snp.fa）、
+    // --skip-bad-records（跳过长度不符的样本和数据库记录）、
+    // --tsv（输出距离明细）、--kernel（指定距离计算内核：缺省为压缩数据库上的 packed，
+    // auto 为当前 CPU 上最快的字节内核）、
+    // --within / --within-norm（改为选出与最好结果的 SNP 差异数 / 归一化距离相差不超过阈值的
+    // 全部菌株，此时输出条数不再生效，最多 --cap 条）、
+    // --weights（位点权重文件，按加权距离排名）、
+    // --min-sites（可比较位点少于此数的菌株排在最后，缺省 10）、
+    // --sites / --db-sites（一致性序列与数据库的位点列表，缺省为 RefBuild 的 .sites 旁注和
+    // ./DB/2k.add；两者都在时按位点标识对齐，否则按位置逐位比较）
+    let mut opts = Opts::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => opts.input = args.next().unwrap_or(opts.input),
+            "--db" => opts.db = args.next().unwrap_or(opts.db),
+            "--skip-bad-records" => opts.skip_bad = true,
+            "--tsv" => opts.tsv = true,
+            "--weights" => opts.weights_path = args.next(),
+            "--within" => opts.threshold = Some(Threshold::Distance(parse_value("--within", args.next())?)),
+            "--within-norm" => {
+                opts.threshold = Some(Threshold::Normalized(parse_value("--within-norm", args.next())?))
+            }
+            "--cap" => opts.cap = parse_value("--cap", args.next())?,
+            "--min-sites" => opts.min_sites = parse_value("--min-sites", args.next())?,
+            "--kernel" => opts.kernel = parse_kernel(args.next().unwrap_or_default())?,
+            "--sites" => opts.sites_path = Some(parse_value("--sites", args.next())?),
+            "--db-sites" => opts.db_sites_path = Some(parse_value("--db-sites", args.next())?),
+            // 只有纯数字才是输出条数，拼错的参数不会把条数悄悄改掉
+            _ => match arg.parse() {
+                Ok(n) => opts.output_count = n,
+                Err(_) => eprintln!("忽略未知参数: {}", arg),
+            },
+        }
+    }
+    Ok(opts)
+}
+
+/// 对一致性序列中的每个样本在数据库中评分，把地址列表或 TSV 明细写到 `writer`
+fn score(opts: &Opts, writer: &mut impl Write) -> Result<(), ScoreError> {
+    // 1. 读取参考序列：每条记录一个样本（RefBuild --minor 时还有次要菌株一条），
+    //    各条须与第一条等长
+    let mut ref_records = parse_fasta_mmap(&opts.input)?;
+    let expected = ref_records.first().ok_or(ScoreError::NoConsensus)?.seq.len();
+    let records = mismatched(ref_records.iter().map(|r| (r.id.as_str(), r.seq.len())), expected);
+    if !records.is_empty() {
+        if !opts.skip_bad {
+            return Err(ScoreError::ConsensusMismatch { expected, records });
+        }
+        eprintln!("警告: {}\n以上样本已跳过", ScoreError::ConsensusMismatch { expected, records });
+        ref_records.retain(|r| r.seq.len() == expected);
+    }
+
+    // 2. 有两边的位点列表时按位点标识对齐，数据库各列的顺序为准
+    let sites_path = opts.sites_path.clone().or_else(|| {
+        let path = align::sidecar(&opts.input);
+        path.exists().then(|| path.to_string_lossy().into_owned())
+    });
+    let db_sites_path = opts
+        .db_sites_path
+        .clone()
+        .or_else(|| Path::new(DB_SITES).exists().then(|| DB_SITES.to_string()));
+    let site_map = match (&sites_path, &db_sites_path) {
+        (Some(sites_path), Some(db_sites_path)) => {
+            Some(site_map(sites_path, db_sites_path, expected)?)
+        }
+        _ => None,
     };
+    let aligned = site_map.as_ref().map_or(expected, SiteMap::len);
 
-    // 1. 读取参考序列
-    let ref_records = parse_fasta_mmap("./output/really-ref.fa");
-    let ref_record = &ref_records[0];
-    let ref_seq = Arc::new(ref_record.seq.clone());
+    // 3. 读取数据库序列并校验长度，所有样本共用
+    let db = Db::open(Path::new(&opts.db), opts.kernel)?;
+    let records = mismatched((0..db.len()).map(|i| (db.id(i), db.seq_len(i))), aligned);
+    if !records.is_empty() {
+        if !opts.skip_bad {
+            return Err(ScoreError::LengthMismatch { expected: aligned, records });
+        }
+        eprintln!("警告: {}\n以上记录已跳过", ScoreError::LengthMismatch { expected: aligned, records });
+    }
+    let keep: Vec<usize> = (0..db.len()).filter(|&i| db.seq_len(i) == aligned).collect();
+    let weights = opts.weights_path.as_deref().map(|p| read_weights(p, expected)).transpose()?;
+    let weights = match (&site_map, weights) {
+        (Some(map), Some(w)) => Some(map.apply(&w, 0.0)),
+        (_, weights) => weights,
+    };
 
-    // 2. 并行处理查询序列
-    let query_records = parse_fasta_mmap("./DB/2k-snp.fa");
+    if opts.tsv && weights.is_some() {
+        writeln!(writer, "sample\tid\tdistance\tsites\tw_distance\tw_sites\tnorm_distance\trank")?;
+    } else if opts.tsv {
+        writeln!(writer, "sample\tid\tdistance\tsites\tnorm_distance\trank")?;
+    } else {
+        writeln!(writer, "address")?;
+    }
 
-    // 预分配结果数组
-    let mut results: Vec<(String, usize)> = Vec::with_capacity(query_records.len());
+    // 4. 每个样本各自并行计算距离，按 [`Hit::score`] 选出前 k 个（阈值模式下先在全部结果中
+    //    按阈值自身的度量筛选，k 为 --cap），与第 k 名并列的全部保留；并列者名次相同（1, 2, 2, 4）。
+    //    地址列表依次去重合并，TSV 为长表，按样本分块输出
+    let k = if opts.threshold.is_some() { opts.cap } else { opts.output_count };
+    let mut selected: Vec<&str> = Vec::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    for ref_record in &ref_records {
+        let seq = match &site_map {
+            Some(map) => map.apply(&ref_record.seq, b'-'),
+            None => ref_record.seq.clone(),
+        };
+        let query = Query::new(&seq, weights.as_deref(), opts.min_sites);
+        let hits = db.hits(&query, &keep);
+        let (results, admitted) = match opts.threshold {
+            Some(threshold) => rank::within(hits, threshold, k),
+            None => (rank::select(hits, k), 0),
+        };
 
-    // 使用并行迭代器处理
-    query_records.into_par_iter().map(|record| {
-        let distance = calculate_snp_distance(&ref_seq, &record.seq);
-        (record.id, distance)
-    }).collect_into_vec(&mut results);
+        // 标题行第一个词（RefBuild 在其后附加 freq=）
+        let sample = ref_record.id.split_whitespace().next().unwrap_or("");
+        if results.len() > k {
+            eprintln!("{}: 第 {} 名有并列，共输出 {} 个", sample, k, results.len());
+        }
+        if admitted > results.len() {
+            eprintln!(
+                "{}: 阈值内有 {} 个菌株，超过上限 {}，只输出前 {} 个",
+                sample, admitted, k, results.len()
+            );
+        }
+        let mut rank = 0;
+        for (i, hit) in results.iter().enumerate() {
+            if i == 0 || hit.score() != results[i - 1].score() {
+                rank = i + 1;
+            }
+            if opts.tsv {
+                let norm = hit.normalized().map_or("NA".to_string(), |v| format!("{:.6}", v));
+                let weighted = hit.weighted.map_or(String::new(), |(d, n)| format!("{:.4}\t{:.4}\t", d, n));
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}{}\t{}",
+                    sample, hit.id, hit.distance, hit.sites, weighted, norm, rank
+                )?;
+            } else if seen.insert(hit.id) {
+                selected.push(hit.id);
+            }
+        }
+    }
 
-    // 3. 并行排序
-    results.par_sort_unstable_by_key(|&(_, dist)| dist);
+    // 5. 高效输出
+    for id in selected {
+        writeln!(writer, "{}", id)?;
+    }
+    Ok(())
+}
 
-    // 4. 高效输出
-    let stdout = std::io::stdout();
-    let mut writer = BufWriter::new(stdout.lock());
-    writeln!(writer, "address").unwrap();
-    for (id, _) in results.into_iter().take(output_count) {
-        writeln!(writer, "{}", id).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::tests::random_seq;
+
+    #[test]
+    fn packed_and_fasta_paths_agree() {
+        let dir = std::env::temp_dir().join(format!("jf_score_db_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta = dir.join("db.fa");
+        let mut state = 0x51_7cc1_b727_220a;
+        let mut text = String::new();
+        for i in 0..30 {
+            let seq = random_seq(&mut state, 300);
+            text += &format!(">s{}\n{}\n", i, String::from_utf8(seq).unwrap());
+        }
+        std::fs::write(&fasta, text).unwrap();
+
+        // 参考序列含兼并码、N 和小写碱基
+        let mut ref_seq = random_seq(&mut state, 300);
+        ref_seq[..6].copy_from_slice(b"MKnvbS");
+        let weights: Vec<f64> = (0..300).map(|i| (i % 4) as f64 * 0.5).collect();
+        let query = Query::new(&ref_seq, Some(&weights), 10);
+        let keep: Vec<usize> = (0..30).collect();
+
+        let run = |kernel| {
+            let db = Db::open(&fasta, kernel).unwrap();
+            rank::select(db.hits(&query, &keep), 30)
+                .iter()
+                .map(|h| (h.id.to_string(), h.distance, h.sites, h.weighted))
+                .collect::<Vec<_>>()
+        };
+        let packed = run(None);
+        assert_eq!(packed.len(), 30);
+        for kernel in Kernel::ALL.into_iter().filter(|k| k.is_available()) {
+            assert_eq!(run(Some(kernel)), packed, "{} 内核", kernel.name());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_n_is_missing() {
+        let dir = std::env::temp_dir().join(format!("jf_score_n_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta = dir.join("db.fa");
+        std::fs::write(&fasta, ">s1\nAAAAAAAAAA\n>s2\nCCCCCCCCCC\n").unwrap();
+        let query = Query::new(b"NNNNNNNNNA", None, 1);
+        for kernel in [None, Some(Kernel::Scalar)] {
+            let db = Db::open(&fasta, kernel).unwrap();
+            let hits = rank::select(db.hits(&query, &[0, 1]), 2);
+            let got: Vec<(&str, usize, usize)> = hits.iter().map(|h| (h.id, h.distance, h.sites)).collect();
+            assert_eq!(got, [("s1", 0, 1), ("s2", 1, 1)]);
+            assert_eq!(hits[1].normalized(), Some(1.0));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_refbuild_support_sidecar() {
+        // RefBuild --input tests/data/sample-stats.txt --output sample.fa --sample s 写出的旁注，
+        // 没有表头，取最后一列（主等位频率）
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/sample.support");
+        let weights = read_weights(path, 5).unwrap();
+        assert_eq!(weights, [0.9677, 1.0, 0.5, 0.0, 1.0]);
+        assert!(matches!(read_weights(path, 6), Err(ScoreError::Weights(..))));
+    }
+
+    #[test]
+    fn only_first_data_line_may_be_a_header() {
+        let dir = std::env::temp_dir().join(format!("jf_score_w_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("w.tsv");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "# 注释\n\nsite\tweight\nchr1:1\t0.5\n\nchr1:2\t2\n").unwrap();
+        assert_eq!(read_weights(path, 2).unwrap(), [0.5, 2.0]);
+
+        // 第二个数据行起的非数字行报错，不再当作表头跳过
+        for text in ["site\tweight\nsite\tweight\nchr1:1\t0.5\n", "chr1:1\t0.5\nchr1:2\tNA\n"] {
+            std::fs::write(path, text).unwrap();
+            let e = read_weights(path, 1).unwrap_err().to_string();
+            assert!(e.contains("第 2 行无法解析权重"), "{}", e);
+        }
+        std::fs::write(path, "chr1:1\t-1\n").unwrap();
+        assert!(read_weights(path, 1).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// 临时目录里的数据库和一致性序列，返回目录和指向它们的参数
+    fn scoring_fixture(tag: &str, db: &str, consensus: &str, args: &[&str]) -> (std::path::PathBuf, Opts) {
+        let dir = std::env::temp_dir().join(format!("jf_score_{}_{}", tag, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("db.fa"), db).unwrap();
+        std::fs::write(dir.join("ref.fa"), consensus).unwrap();
+        let mut opts = parse_args(args.iter().map(|s| s.to_string())).unwrap();
+        opts.db = dir.join("db.fa").to_string_lossy().into_owned();
+        opts.input = dir.join("ref.fa").to_string_lossy().into_owned();
+        (dir, opts)
     }
-    writer.flush().unwrap();
-}
\ No newline at end of file
+
+    fn scored(opts: &Opts) -> Result<String, ScoreError> {
+        let mut out = Vec::new();
+        score(opts, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    const DB: &str = ">d1\nAAAAAAAA\n>d2\nAAAAAACC\n>d3\nCCCCCCCC\n>d4\nAAAACCCC\n";
+
+    #[test]
+    fn scores_every_consensus_record() {
+        let consensus = ">x freq=0.700\nAAAAAAAA\n>y\nCCCCCCCC\n>z\nAAAACCCC\n";
+        let (dir, opts) = scoring_fixture("multi", DB, consensus, &["3", "--min-sites", "1"]);
+        // 地址列表按样本依次去重合并；z 的第 3 名 d1、d3 并列，都保留
+        assert_eq!(scored(&opts).unwrap(), "address\nd1\nd2\nd4\nd3\n");
+
+        let (_, opts) = scoring_fixture("multi", DB, consensus, &["2", "--tsv", "--min-sites", "1"]);
+        let tsv = scored(&opts).unwrap();
+        let rows: Vec<&str> = tsv.lines().skip(1).collect();
+        assert_eq!(
+            rows,
+            [
+                "x\td1\t0\t8\t0.000000\t1",
+                "x\td2\t2\t8\t0.250000\t2",
+                "y\td3\t0\t8\t0.000000\t1",
+                "y\td4\t4\t8\t0.500000\t2",
+                "z\td4\t0\t8\t0.000000\t1",
+                "z\td2\t2\t8\t0.250000\t2",
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn consensus_length_mismatch() {
+        let consensus = ">x\nAAAAAAAA\n>short\nAAAA\n>y\nCCCCCCCC\n";
+        let (dir, opts) = scoring_fixture("cons", DB, consensus, &["1", "--tsv"]);
+        match scored(&opts) {
+            Err(ScoreError::ConsensusMismatch { expected, records }) => {
+                assert_eq!((expected, records), (8, vec![("short".to_string(), 4)]));
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        let (_, opts) = scoring_fixture("cons", DB, consensus, &["1", "--tsv", "--skip-bad-records"]);
+        let samples: Vec<String> =
+            scored(&opts).unwrap().lines().skip(1).map(|l| l.split('\t').next().unwrap().to_string()).collect();
+        assert_eq!(samples, ["x", "y"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn database_length_mismatch() {
+        let db = format!("{}>bad\nAAAAAAA\n", DB);
+        let consensus = ">x\nAAAAAAAA\n";
+        for kernel in ["packed", "scalar"] {
+            let (dir, opts) = scoring_fixture("db", &db, consensus, &["5", "--kernel", kernel]);
+            match scored(&opts) {
+                Err(ScoreError::LengthMismatch { expected, records }) => {
+                    assert_eq!((expected, records), (8, vec![("bad".to_string(), 7)]));
+                }
+                other => panic!("{}: {:?}", kernel, other.map(|_| ())),
+            }
+
+            let args = ["5", "--kernel", kernel, "--skip-bad-records", "--min-sites", "1"];
+            let (_, opts) = scoring_fixture("db", &db, consensus, &args);
+            assert_eq!(scored(&opts).unwrap(), "address\nd1\nd2\nd4\nd3\n", "{}", kernel);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
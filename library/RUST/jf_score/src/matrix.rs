@@ -0,0 +1,320 @@
+// src/matrix.rs
+//! `jf_score matrix`：序列两两之间的 SNP 距离矩阵
+//!
+//! 用于挑选数据库代表菌株、判断候选菌株是否近乎重复。输入缺省为数据库
+//! `./DB/2k-snp.fa`，也可以是若干样本的一致性序列；各条须等长。
+//! 矩阵按 [`TILE`] × [`TILE`] 分块，一行分块（条带）里的块交给 rayon 并行，
+//! 块内两两比较时参与的序列都在缓存里；每条带算完即输出，不保留整个矩阵。
+//! TSV 只算上三角；PHYLIP 的方阵逐行需要全部列，每条带连同下三角一起重算，
+//! 计算量加倍，内存只有 [`TILE`] × n。
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::kernel::Kernel;
+use crate::packed::{self, PackedDb};
+use crate::{canonical, mismatched, parse_fasta_mmap, parse_kernel, ScoreError, DB_PATH};
+
+/// 分块边长
+const TILE: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    /// 方阵，第一行为序列数
+    Phylip,
+    /// 上三角逐对一行：a、b、距离、可比较位点数、归一化距离
+    Tsv,
+}
+
+/// 参与比较的序列：缺省为压缩表示，`--kernel` 指定字节内核时用 FASTA 原文
+enum Seqs {
+    Packed(PackedDb),
+    /// 已按 [`canonical`] 统一，与压缩表示一致
+    Bytes(Vec<String>, Vec<Vec<u8>>, Kernel),
+}
+
+impl Seqs {
+    fn len(&self) -> usize {
+        match self {
+            Seqs::Packed(db) => db.len(),
+            Seqs::Bytes(ids, _, _) => ids.len(),
+        }
+    }
+
+    fn id(&self, i: usize) -> &str {
+        match self {
+            Seqs::Packed(db) => db.id(i),
+            Seqs::Bytes(ids, _, _) => &ids[i],
+        }
+    }
+
+    fn seq_len(&self, i: usize) -> usize {
+        match self {
+            Seqs::Packed(db) => db.seq_len(i),
+            Seqs::Bytes(_, seqs, _) => seqs[i].len(),
+        }
+    }
+
+    /// 返回 (SNP 距离, 可比较位点数)
+    fn distance(&self, i: usize, j: usize) -> (usize, usize) {
+        match self {
+            Seqs::Packed(db) => packed::distance(&db.record(i), &db.record(j)),
+            Seqs::Bytes(_, seqs, kernel) => kernel.distance(&seqs[i], &seqs[j]),
+        }
+    }
+}
+
+/// 第 `a` 行分块：行 i 取 `a * TILE` 起的 [`TILE`] 行，每行为 `cols(i, b)` 给出的
+/// 列分块 b 内各列的 (距离, 可比较位点数)
+///
+/// 同一行分块里的各个块（列分块 `blocks`）并行计算，整体只保留这一条带，
+/// 按行顺序输出后即可丢弃
+fn tiles<C>(seqs: &Seqs, a: usize, blocks: Range<usize>, cols: C) -> Vec<Vec<(u32, u32)>>
+where
+    C: Fn(usize, usize) -> Range<usize> + Sync,
+{
+    let n = seqs.len();
+    let rows = a * TILE..((a + 1) * TILE).min(n);
+    let blocks: Vec<Vec<Vec<(u32, u32)>>> = blocks
+        .into_par_iter()
+        .map(|b| {
+            rows.clone()
+                .map(|i| {
+                    cols(i, b)
+                        .map(|j| {
+                            if i == j {
+                                return (0, 0);
+                            }
+                            let (d, s) = seqs.distance(i, j);
+                            (d as u32, s as u32)
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    (0..rows.len())
+        .map(|r| blocks.iter().flat_map(|block| block[r].iter().copied()).collect())
+        .collect()
+}
+
+/// 上三角条带：行 i 为列 j > i
+fn strip(seqs: &Seqs, a: usize) -> Vec<Vec<(u32, u32)>> {
+    let n = seqs.len();
+    tiles(seqs, a, a..n.div_ceil(TILE), |i, b| (i + 1).max(b * TILE)..((b + 1) * TILE).min(n))
+}
+
+/// 整行条带：行 i 为全部 n 列，对角线为 (0, 0)
+fn band(seqs: &Seqs, a: usize) -> Vec<Vec<(u32, u32)>> {
+    let n = seqs.len();
+    tiles(seqs, a, 0..n.div_ceil(TILE), |_, b| b * TILE..((b + 1) * TILE).min(n))
+}
+
+/// 输出的数值：原始距离，或每个可比较位点的差异数（没有可比较位点为 NA）
+fn value(d: u32, s: u32, normalized: bool) -> String {
+    if !normalized {
+        d.to_string()
+    } else if s == 0 {
+        "NA".to_string()
+    } else {
+        format!("{:.6}", d as f64 / s as f64)
+    }
+}
+
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), ScoreError> {
+    // 参数：--input（FASTA，缺省数据库）、--format phylip|tsv（缺省 phylip）、
+    // --normalized（PHYLIP 中写每个可比较位点的差异数）、--kernel（同评分模式）
+    let mut input = DB_PATH.to_string();
+    let mut format = Format::Phylip;
+    let mut normalized = false;
+    let mut kernel: Option<Kernel> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => input = args.next().unwrap_or(input),
+            "--format" => {
+                format = match args.next().unwrap_or_default().as_str() {
+                    "phylip" => Format::Phylip,
+                    "tsv" => Format::Tsv,
+                    other => return Err(ScoreError::Usage("--format", other.to_string())),
+                }
+            }
+            "--normalized" => normalized = true,
+            "--kernel" => kernel = parse_kernel(args.next().unwrap_or_default())?,
+            _ => eprintln!("忽略未知参数: {}", arg),
+        }
+    }
+
+    // 只有数据库缓存 .pack，样本一致性序列等其它输入在内存中压缩
+    let is_db = matches!(
+        (fs::canonicalize(&input), fs::canonicalize(DB_PATH)),
+        (Ok(a), Ok(b)) if a == b
+    );
+    let seqs = match kernel {
+        None if is_db => Seqs::Packed(PackedDb::open_or_build(Path::new(&input))?),
+        None => Seqs::Packed(PackedDb::in_memory(Path::new(&input))?),
+        Some(kernel) => {
+            let (ids, seqs) = parse_fasta_mmap(&input)?
+                .into_iter()
+                .map(|r| {
+                    let seq = r.seq.iter().map(|&b| canonical(b)).collect();
+                    (r.id, seq)
+                })
+                .unzip();
+            Seqs::Bytes(ids, seqs, kernel)
+        }
+    };
+    if seqs.len() > 0 {
+        let expected = seqs.seq_len(0);
+        let records = mismatched((0..seqs.len()).map(|i| (seqs.id(i), seqs.seq_len(i))), expected);
+        if !records.is_empty() {
+            return Err(ScoreError::MatrixMismatch { expected, records });
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    match format {
+        Format::Phylip => write_phylip(&mut writer, &seqs, normalized)?,
+        Format::Tsv => write_tsv(&mut writer, &seqs)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// 标题行第一个词作为名字
+fn name(seqs: &Seqs, i: usize) -> &str {
+    seqs.id(i).split_whitespace().next().unwrap_or("")
+}
+
+/// 方阵：每条带按整行算好即输出
+fn write_phylip(w: &mut impl Write, seqs: &Seqs, normalized: bool) -> io::Result<()> {
+    let n = seqs.len();
+    writeln!(w, "{}", n)?;
+    for a in 0..n.div_ceil(TILE) {
+        for (r, row) in band(seqs, a).into_iter().enumerate() {
+            let i = a * TILE + r;
+            write!(w, "{}", name(seqs, i))?;
+            for (j, (d, s)) in row.into_iter().enumerate() {
+                if i == j {
+                    write!(w, " 0")?;
+                } else {
+                    write!(w, " {}", value(d, s, normalized))?;
+                }
+            }
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+/// 上三角逐对输出，每条带算完就写
+fn write_tsv(w: &mut impl Write, seqs: &Seqs) -> io::Result<()> {
+    let n = seqs.len();
+    writeln!(w, "a\tb\tdistance\tsites\tnorm_distance")?;
+    for a in 0..n.div_ceil(TILE) {
+        for (r, row) in strip(seqs, a).into_iter().enumerate() {
+            let i = a * TILE + r;
+            for (j, (d, s)) in (i + 1..n).zip(row) {
+                writeln!(w, "{}\t{}\t{}\t{}\t{}", name(seqs, i), name(seqs, j), d, s, value(d, s, true))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 跨过两个分块边界的 n 条伪随机序列，含缺失位点
+    fn sample(n: usize) -> Seqs {
+        let mut x = 0x9e37_79b9_7f4a_7c15u64;
+        let seqs = (0..n)
+            .map(|_| {
+                (0..40)
+                    .map(|_| {
+                        x ^= x << 13;
+                        x ^= x >> 7;
+                        x ^= x << 17;
+                        b"ACGT-"[(x % 5) as usize]
+                    })
+                    .collect()
+            })
+            .collect();
+        let ids = (0..n).map(|i| format!("s{} desc", i)).collect();
+        Seqs::Bytes(ids, seqs, Kernel::Scalar)
+    }
+
+    fn pair(seqs: &Seqs, i: usize, j: usize) -> (u32, u32) {
+        let (d, s) = seqs.distance(i, j);
+        (d as u32, s as u32)
+    }
+
+    #[test]
+    fn strips_cover_upper_triangle_in_order() {
+        let seqs = sample(2 * TILE + 5);
+        let n = seqs.len();
+        let mut got = Vec::new();
+        for a in 0..n.div_ceil(TILE) {
+            let rows = strip(&seqs, a);
+            assert_eq!(rows.len(), (n - a * TILE).min(TILE));
+            for (r, row) in rows.into_iter().enumerate() {
+                let i = a * TILE + r;
+                got.extend((i + 1..n).zip(row).map(|(j, v)| (i, j, v)));
+            }
+        }
+        let expected: Vec<_> =
+            (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).map(|(i, j)| (i, j, pair(&seqs, i, j))).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn phylip_is_symmetric() {
+        let seqs = sample(TILE + 3);
+        let n = seqs.len();
+        for normalized in [false, true] {
+            let mut out = Vec::new();
+            write_phylip(&mut out, &seqs, normalized).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            let mut lines = text.lines();
+            assert_eq!(lines.next(), Some(n.to_string().as_str()));
+            let rows: Vec<Vec<&str>> = lines.map(|l| l.split(' ').collect()).collect();
+            assert_eq!(rows.len(), n);
+            for (i, row) in rows.iter().enumerate() {
+                assert_eq!(row.len(), n + 1);
+                assert_eq!(row[0], format!("s{}", i));
+                assert_eq!(row[i + 1], "0");
+                for j in 0..n {
+                    assert_eq!(row[j + 1], rows[j][i + 1], "({}, {})", i, j);
+                    if i != j {
+                        let (d, s) = pair(&seqs, i, j);
+                        assert_eq!(row[j + 1], value(d, s, normalized));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tsv_lists_each_pair_once() {
+        let seqs = sample(TILE + 1);
+        let mut out = Vec::new();
+        write_tsv(&mut out, &seqs).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let n = seqs.len();
+        assert_eq!(text.lines().count(), 1 + n * (n - 1) / 2);
+        let (d, s) = pair(&seqs, 0, n - 1);
+        let last_of_first_row = format!("s0\ts{}\t{}\t{}\t{}", n - 1, d, s, value(d, s, true));
+        assert_eq!(text.lines().nth(n - 1), Some(last_of_first_row.as_str()));
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        let args = ["--format", "phylp"].map(String::from).into_iter();
+        assert!(matches!(run(args), Err(ScoreError::Usage("--format", v)) if v == "phylp"));
+    }
+}
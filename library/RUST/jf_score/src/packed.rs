@@ -0,0 +1,421 @@
+// src/packed.rs
+//! 数据库序列的位压缩表示与对应的距离内核
+//!
+//! 每 64 个位点用三个 u64 表示：碱基编码的低位、高位（A=00、C=01、G=10、T=11）
+//! 和有效位（ACGT 为 1；`-`、N 等非碱基字符按缺失处理为 0）。距离为
+//! `popcount(((r.lo ^ q.lo) | (r.hi ^ q.hi)) & r.valid & q.valid)`，
+//! 可比较位点数为 `popcount(r.valid & q.valid)`。
+//!
+//! 压缩后的数据库第一次运行时写在 FASTA 旁边（`<fasta>.pack`），
+//! 之后直接内存映射；FASTA 的大小或修改时间变化时自动重建。
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use memmap2::Mmap;
+
+use crate::parse_fasta_mmap;
+
+const MAGIC: &[u8; 8] = b"JFSNPPK1";
+/// 按本机字节序写入，读回不一致时重建
+const ENDIAN_MARK: u64 = 0x0102_0304_0506_0708;
+const HEADER_WORDS: usize = 8;
+
+/// 碱基的 2 位编码
+fn code(b: u8) -> Option<u64> {
+    match b.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// 一条序列的三个位平面（各 `words` 个 u64）
+pub struct PackedView<'a> {
+    lo: &'a [u64],
+    hi: &'a [u64],
+    valid: &'a [u64],
+}
+
+impl PackedView<'_> {
+    /// 第 `i` 个位点的碱基集合（A=1、C=2、G=4、T=8），缺失为 0
+    pub fn mask(&self, i: usize) -> u8 {
+        let (w, b) = (i / 64, i % 64);
+        match self.valid.get(w) {
+            Some(v) if v >> b & 1 == 1 => {
+                let c = (self.lo[w] >> b & 1) | (self.hi[w] >> b & 1) << 1;
+                1 << c
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// 自有内存的压缩序列（用于参考序列）
+pub struct PackedSeq {
+    lo: Vec<u64>,
+    hi: Vec<u64>,
+    valid: Vec<u64>,
+}
+
+impl PackedSeq {
+    pub fn pack(seq: &[u8]) -> Self {
+        let words = seq.len().div_ceil(64);
+        let mut p = PackedSeq { lo: vec![0; words], hi: vec![0; words], valid: vec![0; words] };
+        p.fill(seq);
+        p
+    }
+
+    fn fill(&mut self, seq: &[u8]) {
+        for (i, &b) in seq.iter().enumerate() {
+            if let Some(c) = code(b) {
+                let (w, bit) = (i / 64, i % 64);
+                self.lo[w] |= (c & 1) << bit;
+                self.hi[w] |= (c >> 1) << bit;
+                self.valid[w] |= 1 << bit;
+            }
+        }
+    }
+
+    pub fn view(&self) -> PackedView<'_> {
+        PackedView { lo: &self.lo, hi: &self.hi, valid: &self.valid }
+    }
+}
+
+/// 返回 (SNP 距离, 可比较位点数)，与字节内核在只含 ACGT/`-` 的序列上结果相同
+pub fn distance(r: &PackedView, q: &PackedView) -> (usize, usize) {
+    let mut distance = 0;
+    let mut sites = 0;
+    let words = r.valid.len().min(q.valid.len());
+    for w in 0..words {
+        let both = r.valid[w] & q.valid[w];
+        let diff = (r.lo[w] ^ q.lo[w]) | (r.hi[w] ^ q.hi[w]);
+        distance += (diff & both).count_ones() as usize;
+        sites += both.count_ones() as usize;
+    }
+    (distance, sites)
+}
+
+/// 加权版本：返回 (差异位点权重和, 可比较位点权重和)，只遍历可比较位点
+pub fn weighted_distance(r: &PackedView, q: &PackedView, weights: &[f64]) -> (f64, f64) {
+    let mut distance = 0.0;
+    let mut sites = 0.0;
+    let words = r.valid.len().min(q.valid.len());
+    for w in 0..words {
+        let diff = (r.lo[w] ^ q.lo[w]) | (r.hi[w] ^ q.hi[w]);
+        let mut both = r.valid[w] & q.valid[w];
+        while both != 0 {
+            let bit = both.trailing_zeros();
+            let weight = weights[w * 64 + bit as usize];
+            sites += weight;
+            if diff >> bit & 1 == 1 {
+                distance += weight;
+            }
+            both &= both - 1;
+        }
+    }
+    (distance, sites)
+}
+
+/// 压缩数据库的存放：内存映射的缓存文件，或无法写缓存时在内存中生成的同样布局
+enum Store {
+    Mapped(Mmap),
+    /// 按 u64 存放保证 8 字节对齐；第二项为有效字节数
+    Memory(Vec<u64>, usize),
+}
+
+impl Store {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Store::Mapped(mmap) => mmap,
+            Store::Memory(words, len) => {
+                let bytes =
+                    unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8) };
+                &bytes[..*len]
+            }
+        }
+    }
+}
+
+/// 压缩数据库
+///
+/// 文件布局（u64，本机字节序）：8 个字的文件头（魔数、字节序标记、记录数、
+/// 每个位平面的字数、FASTA 大小、FASTA 修改时间、ID 区字节数、保留），
+/// 之后是各记录原始长度、各记录的 lo/hi/valid 位平面，最后是换行分隔的 ID
+pub struct PackedDb {
+    store: Store,
+    n: usize,
+    words: usize,
+    ids: Vec<(usize, usize)>,
+}
+
+impl PackedDb {
+    /// 打开 `<fasta>.pack`；不存在、版本不符或 FASTA 已更新时先重建。
+    /// 缓存写不进去（只读或共享的数据库目录）时改为在内存中压缩
+    pub fn open_or_build(fasta: &Path) -> io::Result<PackedDb> {
+        let pack = pack_path(fasta);
+        let stamp = source_stamp(fasta)?;
+        if let Ok(db) = PackedDb::open(&pack, stamp) {
+            return Ok(db);
+        }
+        match build(fasta, &pack, stamp).and_then(|_| PackedDb::open(&pack, stamp)) {
+            Ok(db) => Ok(db),
+            Err(e) => {
+                eprintln!("无法写入压缩数据库 '{}'（{}），改为在内存中压缩", pack.display(), e);
+                PackedDb::in_memory(fasta)
+            }
+        }
+    }
+
+    /// 只在内存中压缩，不写缓存文件
+    pub fn in_memory(fasta: &Path) -> io::Result<PackedDb> {
+        let stamp = source_stamp(fasta)?;
+        let mut image = Vec::new();
+        write_image(&mut image, fasta, stamp)?;
+        let mut words = vec![0u64; image.len().div_ceil(8)];
+        unsafe {
+            std::ptr::copy_nonoverlapping(image.as_ptr(), words.as_mut_ptr() as *mut u8, image.len());
+        }
+        PackedDb::from_store(Store::Memory(words, image.len()), stamp)
+    }
+
+    fn open(pack: &Path, stamp: (u64, u64)) -> io::Result<PackedDb> {
+        let file = File::open(pack)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        // mmap 起始地址按页对齐，可以直接按 u64 读取
+        if mmap.as_ptr().align_offset(8) != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "压缩数据库未按 8 字节对齐"));
+        }
+        PackedDb::from_store(Store::Mapped(mmap), stamp)
+    }
+
+    fn from_store(store: Store, stamp: (u64, u64)) -> io::Result<PackedDb> {
+        let bytes = store.bytes();
+        let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < HEADER_WORDS * 8 || &bytes[..8] != MAGIC {
+            return Err(bad("不是压缩数据库文件"));
+        }
+        let header = words_at(bytes, 0, HEADER_WORDS);
+        if header[1] != ENDIAN_MARK {
+            return Err(bad("字节序不符"));
+        }
+        if (header[4], header[5]) != stamp {
+            return Err(bad("FASTA 已更新"));
+        }
+        let (n, words, ids_len) = (header[2] as usize, header[3] as usize, header[6] as usize);
+        let data_words = HEADER_WORDS + n + 3 * n * words;
+        if bytes.len() != data_words * 8 + ids_len {
+            return Err(bad("压缩数据库长度不符"));
+        }
+
+        let mut ids = Vec::with_capacity(n);
+        let mut start = data_words * 8;
+        for line in bytes[start..].split(|&b| b == b'\n').take(n) {
+            ids.push((start, line.len()));
+            start += line.len() + 1;
+        }
+        if ids.len() != n || std::str::from_utf8(&bytes[data_words * 8..]).is_err() {
+            return Err(bad("压缩数据库 ID 区损坏"));
+        }
+        Ok(PackedDb { store, n, words, ids })
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn id(&self, i: usize) -> &str {
+        let (start, len) = self.ids[i];
+        // 打开时已校验 UTF-8
+        std::str::from_utf8(&self.store.bytes()[start..start + len]).unwrap_or("")
+    }
+
+    /// 记录在 FASTA 中的原始长度
+    pub fn seq_len(&self, i: usize) -> usize {
+        words_at(self.store.bytes(), HEADER_WORDS + i, 1)[0] as usize
+    }
+
+    pub fn record(&self, i: usize) -> PackedView<'_> {
+        let bytes = self.store.bytes();
+        let base = HEADER_WORDS + self.n + 3 * self.words * i;
+        PackedView {
+            lo: words_at(bytes, base, self.words),
+            hi: words_at(bytes, base + self.words, self.words),
+            valid: words_at(bytes, base + 2 * self.words, self.words),
+        }
+    }
+}
+
+/// 从字节区第 `at` 个 u64 起取 `len` 个 u64（调用方保证对齐和范围）
+fn words_at(bytes: &[u8], at: usize, len: usize) -> &[u64] {
+    let bytes = &bytes[at * 8..(at + len) * 8];
+    unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u64, len) }
+}
+
+fn pack_path(fasta: &Path) -> PathBuf {
+    let mut name = fasta.as_os_str().to_owned();
+    name.push(".pack");
+    PathBuf::from(name)
+}
+
+/// FASTA 的 (大小, 修改时间纳秒)，用来判断压缩数据库是否过期
+fn source_stamp(fasta: &Path) -> io::Result<(u64, u64)> {
+    let meta = fs::metadata(fasta)
+        .map_err(|e| io::Error::new(e.kind(), format!("'{}': {}", fasta.display(), e)))?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    Ok((meta.len(), mtime))
+}
+
+/// 由 FASTA 生成压缩数据库：先写临时文件再改名，并发运行时不会读到半个文件
+fn build(fasta: &Path, pack: &Path, stamp: (u64, u64)) -> io::Result<()> {
+    let mut tmp = pack.as_os_str().to_owned();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let written = File::create(&tmp).and_then(|file| {
+        let mut w = BufWriter::new(file);
+        let n = write_image(&mut w, fasta, stamp)?;
+        w.flush()?;
+        Ok(n)
+    });
+    let n = match written.and_then(|n| fs::rename(&tmp, pack).map(|_| n)) {
+        Ok(n) => n,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+    eprintln!("已生成压缩数据库 '{}'（{} 条记录）", pack.display(), n);
+    Ok(())
+}
+
+/// 按文件布局写出压缩数据库，返回记录数
+fn write_image(w: &mut impl Write, fasta: &Path, stamp: (u64, u64)) -> io::Result<usize> {
+    let records = parse_fasta_mmap(fasta)?;
+    let n = records.len();
+    let words = records.iter().map(|r| r.seq.len()).max().unwrap_or(0).div_ceil(64);
+    let ids: Vec<u8> = records
+        .iter()
+        .flat_map(|r| r.id.bytes().chain(std::iter::once(b'\n')))
+        .collect();
+
+    w.write_all(MAGIC)?;
+    for v in [ENDIAN_MARK, n as u64, words as u64, stamp.0, stamp.1, ids.len() as u64, 0] {
+        w.write_all(&v.to_ne_bytes())?;
+    }
+    for r in &records {
+        w.write_all(&(r.seq.len() as u64).to_ne_bytes())?;
+    }
+    let mut p = PackedSeq { lo: vec![0; words], hi: vec![0; words], valid: vec![0; words] };
+    for r in &records {
+        for plane in [&mut p.lo, &mut p.hi, &mut p.valid] {
+            plane.fill(0);
+        }
+        p.fill(&r.seq);
+        for plane in [&p.lo, &p.hi, &p.valid] {
+            for v in plane {
+                w.write_all(&v.to_ne_bytes())?;
+            }
+        }
+    }
+    w.write_all(&ids)?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonical;
+    use crate::kernel::tests::random_seq;
+    use crate::kernel::{distance_scalar, weighted_scalar};
+
+    /// 按 [`canonical`] 统一后的字节序列，字节内核在它上面应与压缩内核结果相同
+    fn canon(seq: &[u8]) -> Vec<u8> {
+        seq.iter().map(|&b| canonical(b)).collect()
+    }
+
+    #[test]
+    fn packed_matches_scalar() {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for len in [0, 1, 63, 64, 65, 128, 1000, 4099] {
+            for _ in 0..8 {
+                let r = random_seq(&mut state, len);
+                let q = random_seq(&mut state, len);
+                let (pr, pq) = (PackedSeq::pack(&r), PackedSeq::pack(&q));
+                let expected = distance_scalar(&canon(&r), &canon(&q));
+                assert_eq!(distance(&pr.view(), &pq.view()), expected, "长度 {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_matches_scalar() {
+        let mut state = 0x853c_49e6_748f_ea9b;
+        for len in [1, 64, 65, 1000] {
+            let r = random_seq(&mut state, len);
+            let q = random_seq(&mut state, len);
+            // 权重取 2 的幂，求和顺序不同也没有舍入差异
+            let weights: Vec<f64> = (0..len).map(|i| (1u32 << (i % 5)) as f64 / 4.0).collect();
+            let (pr, pq) = (PackedSeq::pack(&r), PackedSeq::pack(&q));
+            assert_eq!(
+                weighted_distance(&pr.view(), &pq.view(), &weights),
+                weighted_scalar(&canon(&r), &canon(&q), &weights),
+                "长度 {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn mask_reads_back_bases() {
+        let p = PackedSeq::pack(b"ACGT-N");
+        let v = p.view();
+        let masks: Vec<u8> = (0..7).map(|i| v.mask(i)).collect();
+        assert_eq!(masks, [1, 2, 4, 8, 0, 0, 0]);
+    }
+
+    #[test]
+    fn database_round_trip() {
+        let dir = std::env::temp_dir().join(format!("jf_score_pack_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fasta = dir.join("db.fa");
+        fs::write(&fasta, ">s1\nACGT-A\n>s2 extra\nTTGA\n>s3\nAC\nGTAA\n").unwrap();
+
+        for _ in 0..2 {
+            // 第二次直接映射已生成的文件
+            let db = PackedDb::open_or_build(&fasta).unwrap();
+            assert_eq!(db.len(), 3);
+            assert_eq!((db.id(0), db.id(1), db.id(2)), ("s1", "s2 extra", "s3"));
+            assert_eq!((db.seq_len(0), db.seq_len(1), db.seq_len(2)), (6, 4, 6));
+            let r = PackedSeq::pack(b"ACGTAA");
+            assert_eq!(distance(&r.view(), &db.record(0)), distance_scalar(b"ACGTAA", b"ACGT-A"));
+            assert_eq!(distance(&r.view(), &db.record(2)), (0, 6));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_memory_matches_cache() {
+        let dir = std::env::temp_dir().join(format!("jf_score_mem_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fasta = dir.join("db.fa");
+        let mut state = 0x1234_5678_9abc_def1;
+        let mut text = String::new();
+        for i in 0..5 {
+            text += &format!(">r{}\n{}\n", i, String::from_utf8(random_seq(&mut state, 200)).unwrap());
+        }
+        fs::write(&fasta, text).unwrap();
+
+        let mapped = PackedDb::open_or_build(&fasta).unwrap();
+        let memory = PackedDb::in_memory(&fasta).unwrap();
+        assert!(matches!(memory.store, Store::Memory(..)));
+        assert_eq!(mapped.store.bytes(), memory.store.bytes());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+// src/rank.rs
+//! 比较结果的排序与有界 top-k 选择
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use rayon::prelude::*;
+
+/// 一条数据库记录相对一致性序列的比较结果
+pub struct Hit<'a> {
+    pub id: &'a str,
+    /// 可比较位点上的 SNP 差异数
+    pub distance: usize,
+    /// 两条序列都有碱基的位点数
+    pub sites: usize,
+    /// 给了位点权重时为 (差异位点权重和, 可比较位点权重和)
+    pub weighted: Option<(f64, f64)>,
+    /// 可比较位点少于 `--min-sites`：归一化距离靠不住，排在其它结果之后
+    pub sparse: bool,
+}
+
+impl Hit<'_> {
+    /// 每个可比较位点的差异数（有权重时按权重和计算）；没有可比较位点时为 None
+    pub fn normalized(&self) -> Option<f64> {
+        match self.weighted {
+            Some((d, n)) => (n > 0.0).then(|| d / n),
+            None => (self.sites > 0).then(|| self.distance as f64 / self.sites as f64),
+        }
+    }
+
+    /// 差异数，有权重时为差异位点权重和
+    pub fn raw(&self) -> f64 {
+        self.weighted.map_or(self.distance as f64, |(d, _)| d)
+    }
+
+    /// 排名依据：每个可比较位点的差异数（缺失多的菌株不会因为可比较位点少而靠前），
+    /// 同值再按差异数；两者都相同即为并列。可比较位点太少的（如 0/1）不按归一化距离
+    /// 排名，否则几乎没有数据的菌株反而排在最前
+    pub fn score(&self) -> (f64, f64) {
+        let norm = if self.sparse { None } else { self.normalized() };
+        (norm.unwrap_or(f64::INFINITY), self.raw())
+    }
+
+    fn cmp_score(&self, other: &Hit) -> Ordering {
+        let (na, da) = self.score();
+        let (nb, db) = other.score();
+        na.total_cmp(&nb).then(da.total_cmp(&db))
+    }
+}
+
+/// 完整的全序：先按 [`Hit::score`]，并列时可比较位点多的在前，再按 ID，结果与线程调度无关
+impl Ord for Hit<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_score(other)
+            .then(other.sites.cmp(&self.sites))
+            .then(self.id.cmp(other.id))
+    }
+}
+
+impl PartialOrd for Hit<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Hit<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Hit<'_> {}
+
+/// 相对最好结果的候选阈值
+#[derive(Clone, Copy)]
+pub enum Threshold {
+    /// SNP 差异数（有权重时为差异位点权重和）不超过最好结果 + N
+    Distance(f64),
+    /// 每个可比较位点的差异数不超过最好结果 + F
+    Normalized(f64),
+}
+
+impl Threshold {
+    /// 阈值所用的度量；可比较位点太少的结果不参与（为无穷大）
+    fn metric(self, hit: &Hit) -> f64 {
+        match (self, hit.score().0) {
+            (_, f64::INFINITY) => f64::INFINITY,
+            (Threshold::Distance(_), _) => hit.raw(),
+            (Threshold::Normalized(_), norm) => norm,
+        }
+    }
+
+    fn margin(self) -> f64 {
+        match self {
+            Threshold::Distance(n) | Threshold::Normalized(n) => n,
+        }
+    }
+}
+
+/// 保留最好的 k 条结果，以及与第 k 名并列的全部结果
+///
+/// 大顶堆最多 k 条，堆顶为第 k 名；堆满后与第 k 名分数相同的结果放进 `ties`。
+/// 更好的结果进堆时挤出原来的第 k 名：新的第 k 名分数不变则它也并列，
+/// 否则原来的并列结果都落到 k 名之外，清空 `ties`。每条结果只进出一次，
+/// 大量并列时不会反复弹出放回
+pub struct TopK<'a> {
+    k: usize,
+    heap: BinaryHeap<Hit<'a>>,
+    ties: Vec<Hit<'a>>,
+}
+
+impl<'a> TopK<'a> {
+    pub fn new(k: usize) -> Self {
+        TopK { k, heap: BinaryHeap::with_capacity(k), ties: Vec::new() }
+    }
+
+    pub fn push(&mut self, hit: Hit<'a>) {
+        if self.heap.len() < self.k {
+            self.heap.push(hit);
+            return;
+        }
+        let Some(kth) = self.heap.peek() else {
+            return;
+        };
+        match hit.cmp_score(kth) {
+            Ordering::Greater => {}
+            Ordering::Equal => self.ties.push(hit),
+            Ordering::Less => {
+                self.heap.push(hit);
+                let evicted = self.heap.pop().unwrap();
+                if self.heap.peek().is_some_and(|kth| kth.cmp_score(&evicted).is_eq()) {
+                    self.ties.push(evicted);
+                } else {
+                    self.ties.clear();
+                }
+            }
+        }
+    }
+
+    /// 合并两个线程的部分结果
+    pub fn merge(mut self, other: TopK<'a>) -> Self {
+        for hit in other.heap.into_iter().chain(other.ties) {
+            self.push(hit);
+        }
+        self
+    }
+
+    /// 从好到差排列
+    pub fn into_sorted_vec(self) -> Vec<Hit<'a>> {
+        let mut hits = self.heap.into_vec();
+        hits.extend(self.ties);
+        hits.sort_unstable();
+        hits
+    }
+}
+
+/// 并行选出最好的 `k` 条及与第 k 名并列的结果，从好到差排列
+pub fn select<'a>(hits: impl ParallelIterator<Item = Hit<'a>>, k: usize) -> Vec<Hit<'a>> {
+    hits.fold(|| TopK::new(k), |mut top, hit| {
+        top.push(hit);
+        top
+    })
+    .reduce(|| TopK::new(k), TopK::merge)
+    .into_sorted_vec()
+}
+
+/// 在全部结果中按阈值自身的度量筛出与最好结果相差不超过阈值的，再选出最好的 `k` 条
+/// （含与第 k 名并列的）；同时返回阈值内的总数，多于输出条数说明被上限截断
+pub fn within<'a>(
+    hits: impl ParallelIterator<Item = Hit<'a>>,
+    threshold: Threshold,
+    k: usize,
+) -> (Vec<Hit<'a>>, usize) {
+    let hits: Vec<Hit<'a>> = hits.collect();
+    let best = hits.par_iter().map(|h| threshold.metric(h)).reduce(|| f64::INFINITY, f64::min);
+    let limit = best + threshold.margin();
+    let admitted: Vec<Hit<'a>> = hits.into_par_iter().filter(|h| threshold.metric(h) <= limit).collect();
+    let n = admitted.len();
+    (select(admitted.into_par_iter(), k), n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(id: &str, distance: usize, sites: usize) -> Hit<'_> {
+        Hit { id, distance, sites, weighted: None, sparse: false }
+    }
+
+    fn ids(top: TopK<'_>) -> Vec<&str> {
+        top.into_sorted_vec().into_iter().map(|h| h.id).collect()
+    }
+
+    #[test]
+    fn keeps_best_k() {
+        let mut top = TopK::new(2);
+        for h in [hit("a", 5, 10), hit("b", 1, 10), hit("c", 3, 10), hit("d", 9, 10)] {
+            top.push(h);
+        }
+        assert_eq!(ids(top), ["b", "c"]);
+    }
+
+    #[test]
+    fn keeps_all_ties_at_kth() {
+        let mut top = TopK::new(2);
+        for h in [hit("e", 2, 10), hit("a", 1, 10), hit("d", 2, 10), hit("z", 4, 10), hit("c", 2, 10)] {
+            top.push(h);
+        }
+        // 第 2 名 distance = 2 有三条并列，全部保留并按 ID 排
+        assert_eq!(ids(top), ["a", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn many_ties_stay_linear() {
+        let names: Vec<String> = (0..20_000).map(|i| format!("s{:05}", i)).collect();
+        let mut top = TopK::new(20);
+        for name in &names {
+            top.push(hit(name, 7, 100));
+        }
+        let hits = top.into_sorted_vec();
+        assert_eq!(hits.len(), names.len());
+        assert!(hits.windows(2).all(|w| w[0].id < w[1].id));
+
+        // 第 k 名变好后，原来的并列结果全部落选
+        let mut top = TopK::new(20);
+        for name in &names[..5_000] {
+            top.push(hit(name, 7, 100));
+        }
+        for name in &names[5_000..5_020] {
+            top.push(hit(name, 3, 100));
+        }
+        let hits = top.into_sorted_vec();
+        assert_eq!(hits.len(), 20);
+        assert!(hits.iter().all(|h| h.distance == 3));
+    }
+
+    #[test]
+    fn merge_is_order_independent() {
+        let hits = || {
+            vec![hit("a", 1, 10), hit("b", 2, 20), hit("c", 2, 10), hit("d", 1, 10), hit("e", 3, 10)]
+        };
+        let mut one = TopK::new(3);
+        for h in hits() {
+            one.push(h);
+        }
+        let (mut left, mut right) = (TopK::new(3), TopK::new(3));
+        for (i, h) in hits().into_iter().rev().enumerate() {
+            if i % 2 == 0 { left.push(h) } else { right.push(h) }
+        }
+        assert_eq!(ids(one), ids(left.merge(right)));
+    }
+
+    #[test]
+    fn normalized_before_raw_distance() {
+        let mut top = TopK::new(1);
+        top.push(hit("sparse", 1, 2));
+        top.push(hit("dense", 5, 100));
+        top.push(hit("empty", 0, 0));
+        assert_eq!(ids(top), ["dense"]);
+    }
+
+    #[test]
+    fn sparse_hits_rank_last() {
+        let mut top = TopK::new(2);
+        top.push(Hit { sparse: true, ..hit("one-site", 0, 1) });
+        top.push(hit("dense", 5, 100));
+        top.push(hit("worse", 40, 100));
+        assert_eq!(ids(top), ["dense", "worse"]);
+
+        // 阈值的最好结果也不取可比较位点太少的
+        let hits = vec![Hit { sparse: true, ..hit("one-site", 0, 1) }, hit("dense", 5, 100)];
+        let (hits, n) = within(hits.into_par_iter(), Threshold::Distance(1.0), 5);
+        assert_eq!((hits[0].id, n), ("dense", 1));
+    }
+
+    #[test]
+    fn threshold_over_all_hits() {
+        // b 的原始距离与最好的 a 相同，但归一化距离排在 c 之后：先筛选再截断，不会被漏掉
+        let hits = || vec![hit("a", 10, 1000), hit("b", 10, 20), hit("c", 30, 1000), hit("z", 0, 0)];
+        let run = |threshold, k| {
+            let (hits, n) = within(hits().into_par_iter(), threshold, k);
+            (hits.iter().map(|h| h.id).collect::<Vec<_>>(), n)
+        };
+        assert_eq!(run(Threshold::Distance(0.0), 2), (vec!["a", "b"], 2));
+        assert_eq!(run(Threshold::Distance(0.0), 1), (vec!["a"], 2));
+        assert_eq!(run(Threshold::Normalized(0.025), 5), (vec!["a", "c"], 2));
+    }
+}
@@ -0,0 +1,122 @@
+//! 各工具共用的位点标识 `contig:pos`
+//!
+//! 旧数据里只有位置的写法（`12345`）仍然可以解析，此时 contig 为空，
+//! 输出时也保持只有位置。排序先按 contig 名（字典序）再按位置（数值）。
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SiteId {
+    pub contig: String,
+    /// 1-based 位置
+    pub pos: u64,
+}
+
+impl SiteId {
+    pub fn new(contig: &str, pos: u64) -> Self {
+        SiteId { contig: contig.to_string(), pos }
+    }
+
+    /// 解析 `contig:pos` 或单独的 `pos`；contig 名本身可以含 `:`
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        match s.rsplit_once(':') {
+            Some((contig, pos)) if !contig.is_empty() => {
+                Some(SiteId { contig: contig.to_string(), pos: pos.parse().ok()? })
+            }
+            Some(_) => None,
+            None => Some(SiteId { contig: String::new(), pos: s.parse().ok()? }),
+        }
+    }
+
+    /// 解析位点列表的一行：`contig<TAB>pos`，或第一列为 `contig:pos` / `pos`
+    ///
+    /// 第二列不是位置时按第一列解析（如 jf_df 的 poc.txt 之后附加的列）；
+    /// 空行、表头和位置为 0 的行返回 None
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let mut cols = line.split('\t');
+        let first = cols.next()?;
+        let site = match cols.next().and_then(|pos| pos.trim().parse().ok()) {
+            Some(pos) => SiteId::new(first.trim(), pos),
+            None => SiteId::parse(first)?,
+        };
+        (site.pos > 0).then_some(site)
+    }
+}
+
+/// 读取位点列表（如 `2k.add`、RefBuild 的 `.sites` 旁注），保持文件中的顺序，
+/// 格式见 [`SiteId::parse_line`]，无法解析的行跳过
+pub fn read_list<P: AsRef<Path>>(path: P) -> io::Result<Vec<SiteId>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("'{}': {}", path.display(), e)))?;
+    Ok(text.lines().filter_map(SiteId::parse_line).collect())
+}
+
+impl FromStr for SiteId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SiteId::parse(s).ok_or_else(|| format!("无法解析位点标识 '{}'", s))
+    }
+}
+
+impl fmt::Display for SiteId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.contig.is_empty() {
+            write!(f, "{}", self.pos)
+        } else {
+            write!(f, "{}:{}", self.contig, self.pos)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_forms() {
+        assert_eq!(SiteId::parse("chr1:100"), Some(SiteId::new("chr1", 100)));
+        // contig 名本身含 `:` 时按最后一个 `:` 拆分
+        assert_eq!(SiteId::parse("a:b:100"), Some(SiteId::new("a:b", 100)));
+        // 只有位置的旧写法
+        assert_eq!(SiteId::parse(" 12345 "), Some(SiteId::new("", 12345)));
+        assert_eq!(SiteId::parse(":5"), None);
+        assert_eq!(SiteId::parse("chr1:"), None);
+        assert_eq!(SiteId::parse("chr1:x"), None);
+        assert_eq!(SiteId::parse(""), None);
+        assert!("address".parse::<SiteId>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["chr1:100", "a:b:100", "12345"] {
+            assert_eq!(SiteId::parse(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn order_is_contig_then_numeric_position() {
+        let mut sites: Vec<SiteId> =
+            ["chr2:5", "chr10:1", "chr2:40", "chr2:100"].iter().map(|s| s.parse().unwrap()).collect();
+        sites.sort();
+        let sorted: Vec<String> = sites.iter().map(|s| s.to_string()).collect();
+        assert_eq!(sorted, ["chr10:1", "chr2:5", "chr2:40", "chr2:100"]);
+    }
+
+    #[test]
+    fn list_lines() {
+        assert_eq!(SiteId::parse_line("chr1\t100"), Some(SiteId::new("chr1", 100)));
+        assert_eq!(SiteId::parse_line("chr1:100"), Some(SiteId::new("chr1", 100)));
+        assert_eq!(SiteId::parse_line("chr1:100\tA:3,C:1"), Some(SiteId::new("chr1", 100)));
+        assert_eq!(SiteId::parse_line("100"), Some(SiteId::new("", 100)));
+        assert_eq!(SiteId::parse_line("address"), None);
+        assert_eq!(SiteId::parse_line("chr1\t0"), None);
+        assert_eq!(SiteId::parse_line(""), None);
+    }
+}
@@ -5,6 +5,7 @@ use std::error::Error;
 use std::env;
 use memmap2::Mmap;
 use rayon::prelude::*;
+use site_id::SiteId;
 
 type ThreadSafeError = Box<dyn Error + Send + Sync>;
 
@@ -51,17 +52,17 @@ fn main() -> Result<(), ThreadSafeError> {
     writeln!(&mut writer, "{}", target_cols.join("\t"))?;
     
     // 并行处理数据行
-    let mut filtered_lines: Vec<(u32, String)> = lines
+    let mut filtered_lines: Vec<(SiteId, String)> = lines
         .par_bridge()
         .filter_map(|line| {
             let parts: Vec<&str> = line.split('\t').collect();
-            if parts.is_empty() || !target_rows.contains(parts[0]) {
+
+            // 解析第一列位点标识（contig:pos）用于匹配和排序
+            let address = SiteId::parse(parts[0])?;
+            if !target_rows.contains(&address) {
                 return None;
             }
             
-            // 解析第一列为数字用于排序
-            let address = parts[0].parse::<u32>().ok()?;
-            
             // 按header_indices顺序提取字段
             let extracted_fields: Vec<&str> = header_indices
                 .iter()
@@ -70,16 +71,17 @@ fn main() -> Result<(), ThreadSafeError> {
             
             // 检查行内字段是否全为"-"
             let mut all_dash = true;
-            let mut base_value = None;
+            let mut base_value: Option<&str> = None;
             let mut all_equal = true;
             
-            // 跳过第一个字段（行ID）
+            // 跳过第一个字段（行ID）。单元格为碱基、插入 `+AT` 或缺失 `-G`，
+            // 只有单独的 `-` 表示无数据；等位不区分大小写
             for field in extracted_fields.iter().skip(1) {
                 if *field != "-" {
                     all_dash = false;
                     
                     match base_value {
-                        Some(base) if base != *field => {
+                        Some(base) if !base.eq_ignore_ascii_case(field) => {
                             all_equal = false;
                             // 提前退出：发现不等字段
                             break;
@@ -101,8 +103,8 @@ fn main() -> Result<(), ThreadSafeError> {
         })
         .collect();
     
-    // 按第一列数字排序
-    filtered_lines.par_sort_unstable_by_key(|(address, _)| *address);
+    // 按位点排序（contig 名、位置）
+    filtered_lines.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
     
     // 写入排序后的数据
     for (_, line) in filtered_lines {
@@ -124,15 +126,7 @@ fn read_ids_to_vec(filename: &str) -> Result<Vec<String>, ThreadSafeError> {
         .collect())
 }
 
-// 快速查找的行ID读取函数
-fn read_ids_to_hashset(filename: &str) -> Result<HashSet<String>, ThreadSafeError> {
-    let file = File::open(filename)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-    Ok(mmap
-        .split(|&c| c == b'\n')
-        .par_bridge()
-        .filter_map(|line| std::str::from_utf8(line).ok())
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect())
+// 快速查找的行ID读取函数：`contig<TAB>pos` 或第一列 `contig:pos`，与 jf_df 的位点列表相同
+fn read_ids_to_hashset(filename: &str) -> Result<HashSet<SiteId>, ThreadSafeError> {
+    Ok(site_id::read_list(filename)?.into_iter().collect())
 }
\ No newline at end of file
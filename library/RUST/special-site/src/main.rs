@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, BufWriter, Write},
@@ -20,6 +21,16 @@ fn cleanup() -> std::io::Result<()> {
         .try_for_each(fs::remove_file)
 }
 
+/// 矩阵单元格的等位写法：碱基、插入 `+AT`、缺失 `-G`；单独的 `-` 表示无数据。
+/// 统一转大写，与 jf_df 统计表中的等位一致
+fn allele(cell: &str) -> Cow<'_, str> {
+    if cell.bytes().any(|b| b.is_ascii_lowercase()) {
+        Cow::Owned(cell.to_ascii_uppercase())
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
 fn process(path: &str) -> std::io::Result<()> {
     let mut writers = HashMap::new();
     let mut reader = BufReader::new(File::open(path)?);
@@ -43,16 +54,17 @@ fn process(path: &str) -> std::io::Result<()> {
         }
 
         let key = parts[0];
-        let cols = &parts[1..];
+        let cols: Vec<Cow<str>> = parts[1..].iter().map(|c| allele(c)).collect();
 
         // 一次 O(n) 统计
         let mut freq = FxHashMap::default();
-        for &v in cols {
-            *freq.entry(v).or_insert(0) += 1;
+        for v in &cols {
+            *freq.entry(v.as_ref()).or_insert(0) += 1;
         }
 
         // 按需写文件
-        for (i, &val) in cols.iter().enumerate() {
+        for (i, val) in cols.iter().enumerate() {
+            let val = val.as_ref();
             if val == "-" || freq[val] != 1 {
                 continue;
             }